@@ -0,0 +1,181 @@
+use clap::Command;
+use clap_complete::{generate, Shell};
+use clap_complete_nushell::Nushell;
+
+/// Arguments whose value is an existing profile name.
+const PROFILE_NAME_ARGS: &[&str] = &[
+    "activate", "edit", "clone", "pin", "unpin", "set-default", "rename", "delete", "set-token",
+    "clear-token",
+];
+
+/// Hidden flag the generated scripts call to list profile names.
+const COMPLETE_PROFILES_FLAG: &str = "--complete-profiles";
+
+/// Builds the completion script for `shell`, with profile-name arguments
+/// completed by shelling out to the binary's `--complete-profiles` helper.
+pub fn generate_completions(shell: &str, cmd: &mut Command) -> String {
+    let bin = cmd.get_name().to_string();
+    let mut buf = Vec::new();
+
+    match shell {
+        "bash" => generate(Shell::Bash, cmd, &bin, &mut buf),
+        "zsh" => generate(Shell::Zsh, cmd, &bin, &mut buf),
+        "fish" => generate(Shell::Fish, cmd, &bin, &mut buf),
+        "nushell" => generate(Nushell, cmd, &bin, &mut buf),
+        _ => unreachable!("clap restricts --generate-completions values"),
+    }
+
+    let script = String::from_utf8(buf).expect("clap_complete emits UTF-8");
+    let (longs, shorts) = profile_name_flags(cmd);
+
+    match shell {
+        "bash" => wire_bash(&script, &bin, &longs, &shorts),
+        "zsh" => wire_zsh(&script, &bin, &longs, &shorts),
+        "fish" => wire_fish(&script, &bin, &longs),
+        _ => wire_nushell(&script, &bin, &longs),
+    }
+}
+
+fn profile_name_flags(cmd: &Command) -> (Vec<String>, Vec<char>) {
+    let args = cmd
+        .get_arguments()
+        .filter(|arg| PROFILE_NAME_ARGS.contains(&arg.get_id().as_str()));
+
+    let mut longs = Vec::new();
+    let mut shorts = Vec::new();
+    for arg in args {
+        longs.extend(arg.get_long().map(str::to_string));
+        shorts.extend(arg.get_short());
+    }
+
+    (longs, shorts)
+}
+
+fn wire_bash(script: &str, bin: &str, longs: &[String], shorts: &[char]) -> String {
+    let cases: Vec<String> = longs
+        .iter()
+        .map(|long| format!("--{long})"))
+        .chain(shorts.iter().map(|short| format!("-{short})")))
+        .collect();
+
+    let mut out = Vec::new();
+    let mut in_profile_case = false;
+    for line in script.lines() {
+        let trimmed = line.trim();
+        if in_profile_case && trimmed == r#"COMPREPLY=($(compgen -f "${cur}"))"# {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            out.push(format!(
+                r#"{indent}COMPREPLY=($(compgen -W "$({bin} {COMPLETE_PROFILES_FLAG} 2>/dev/null)" -- "${{cur}}"))"#
+            ));
+        } else {
+            out.push(line.to_string());
+        }
+        in_profile_case = cases.iter().any(|case| case == trimmed);
+    }
+
+    out.join("\n") + "\n"
+}
+
+fn wire_zsh(script: &str, bin: &str, longs: &[String], shorts: &[char]) -> String {
+    let prefixes: Vec<String> = longs
+        .iter()
+        .map(|long| format!("'--{long}=["))
+        .chain(shorts.iter().map(|short| format!("'-{short}+[")))
+        .collect();
+    let action = format!(r#"{{compadd -- ${{(f)"$({bin} {COMPLETE_PROFILES_FLAG} 2>/dev/null)"}}}}"#);
+
+    let mut out = Vec::new();
+    for line in script.lines() {
+        if prefixes.iter().any(|prefix| line.starts_with(prefix))
+            && let Some(pos) = line.find("]:")
+            && let Some(default) = line[pos..].find(":_default")
+        {
+            // Only the first value (e.g. OLD for --rename) is an existing profile
+            let at = pos + default;
+            out.push(format!("{}:{}{}", &line[..at], action, &line[at + ":_default".len()..]));
+        } else {
+            out.push(line.to_string());
+        }
+    }
+
+    out.join("\n") + "\n"
+}
+
+fn wire_fish(script: &str, bin: &str, longs: &[String]) -> String {
+    let mut out = Vec::new();
+    for line in script.lines() {
+        let is_profile_arg = longs
+            .iter()
+            .any(|long| line.contains(&format!(" -l {long} ")));
+        if is_profile_arg && line.ends_with(" -r") {
+            out.push(format!("{line} -f -a \"({bin} {COMPLETE_PROFILES_FLAG})\""));
+        } else {
+            out.push(line.to_string());
+        }
+    }
+
+    out.join("\n") + "\n"
+}
+
+fn wire_nushell(script: &str, bin: &str, longs: &[String]) -> String {
+    let completer = format!("nu-complete {bin} profiles");
+
+    let mut out = Vec::new();
+    for line in script.lines() {
+        let trimmed = line.trim_start();
+        let is_profile_arg = longs.iter().any(|long| {
+            trimmed.starts_with(&format!("--{long}:")) || trimmed.starts_with(&format!("--{long}("))
+        });
+
+        if is_profile_arg && let Some(pos) = line.find(": string") {
+            let at = pos + ": string".len();
+            out.push(format!("{}@\"{}\"{}", &line[..at], completer, &line[at..]));
+        } else {
+            out.push(line.to_string());
+        }
+
+        if line.starts_with("module completions {") {
+            out.push(String::new());
+            out.push(format!("  def \"{completer}\" [] {{"));
+            out.push(format!("    ^{bin} {COMPLETE_PROFILES_FLAG} | lines"));
+            out.push("  }".to_string());
+        }
+    }
+
+    out.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_shell_completes_profile_names() {
+        for (shell, marker) in [
+            ("bash", "compgen -W \"$(pulumi-profile-selector --complete-profiles"),
+            ("zsh", "{compadd -- ${(f)\"$(pulumi-profile-selector --complete-profiles"),
+            ("fish", "-a \"(pulumi-profile-selector --complete-profiles)\""),
+            ("nushell", "string@\"nu-complete pulumi-profile-selector profiles\""),
+        ] {
+            let script = generate_completions(shell, &mut crate::build_cli());
+            let wired = script.matches(marker).count();
+
+            // every profile-name flag, plus -a where shells list shorts separately
+            assert!(wired >= PROFILE_NAME_ARGS.len(), "{shell} wired {wired} flags");
+        }
+    }
+
+    #[test]
+    fn test_bash_only_rewrites_profile_cases() {
+        let script = generate_completions("bash", &mut crate::build_cli());
+
+        let after = |case: &str| {
+            let pos = script.find(&format!("                {case})\n")).unwrap();
+            script[pos..].lines().nth(1).unwrap().to_string()
+        };
+        assert!(after("--activate").contains(COMPLETE_PROFILES_FLAG));
+        assert!(after("-a").contains(COMPLETE_PROFILES_FLAG));
+        assert!(!after("--new").contains(COMPLETE_PROFILES_FLAG));
+        assert!(!after("--config").contains(COMPLETE_PROFILES_FLAG));
+    }
+}
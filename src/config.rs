@@ -1,127 +1,3453 @@
-use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Every environment variable an activation may set; deactivation unsets all of them.
+pub const MANAGED_ENV_VARS: &[&str] = &[
+    "PULUMI_BACKEND_URL",
+    "PULUMI_ACCESS_TOKEN",
+    "PULUMI_CONFIG_PASSPHRASE_FILE",
+];
+
+/// Variables a profile's `env` may not set: a profile could otherwise hijack
+/// the shell (`PATH`, `LD_PRELOAD`) or fight the variables managed above.
+pub const RESERVED_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "SHELL",
+    "USER",
+    "PWD",
+    "IFS",
+    "ENV",
+    "BASH_ENV",
+    "PROMPT_COMMAND",
+    "PS1",
+    "PULUMI_HOME",
+    "PULUMI_PROFILES_JSON",
+];
+
+/// Prefixes of loader and shell-function variables, also reserved.
+const RESERVED_ENV_PREFIXES: &[&str] = &["LD_", "DYLD_", "BASH_FUNC_"];
+
+/// Backend URL schemes understood by the Pulumi CLI.
+pub const BACKEND_SCHEMES: &[&str] = &["s3", "gs", "azblob", "file", "https", "http"];
+
+/// Longest profile name accepted by `validate_profile_name`.
+pub const MAX_PROFILE_NAME_LEN: usize = 64;
+
+/// Why a profile operation failed, so callers can tell a missing profile from
+/// a failed write without parsing messages.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Profile '{0}' not found")]
+    ProfileNotFound(String),
+    /// Only the local overlay defines the profile, and the change targets the base file
+    #[error("Profile '{0}' is defined in the local overlay; pass --local to change it there")]
+    OnlyInOverlay(String),
+    #[error("Profile '{0}' already exists")]
+    ProfileExists(String),
+    /// An existing name differs from the new one only in case
+    #[error(
+        "Profile '{name}' collides with existing profile '{existing}' (names are case-insensitive; use --case-sensitive to allow both)"
+    )]
+    NameCollision { name: String, existing: String },
+    #[error("Profile '{name}' already uses backend '{backend}' (use --force to add another)")]
+    BackendInUse { name: String, backend: String },
+    #[error("Profile '{0}' is currently active; deactivate it first (--deactivate) or pass --force")]
+    ProfileActive(String),
+    #[error("{0}")]
+    InvalidName(String),
+    #[error("{0}")]
+    InvalidBackend(String),
+    /// A profile's `env` names a reserved variable or holds an unsafe value
+    #[error("{0}")]
+    InvalidEnvVar(String),
+    #[error("Backend URL '{url}' references ${name} but it is not set")]
+    UnsetVariable { url: String, name: String },
+    #[error("Cannot modify profiles provided via {PROFILES_JSON_ENV}; unset it to manage the profiles file")]
+    ReadOnly,
+    #[error("Cannot modify profiles fetched from {0}; drop --profiles-url to manage the profiles file")]
+    RemoteReadOnly(String),
+    #[error("Invalid {PROFILES_JSON_ENV}")]
+    InvalidInline(#[source] Box<ConfigError>),
+    #[error("Invalid profiles at {0}")]
+    InvalidRemote(String, #[source] Box<ConfigError>),
+    #[error("Profiles file version {0} is newer than this tool supports (version {PROFILES_FILE_VERSION}); please upgrade")]
+    UnsupportedVersion(u32),
+    /// Valid JSON that isn't a profiles file
+    #[error("{0}")]
+    InvalidFile(String),
+    #[error("{context}")]
+    Parse {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to determine home directory")]
+    HomeDirUnavailable,
+    #[error("Group '{0}' is not defined in the profiles file")]
+    UnknownGroup(String),
+    /// A `{{var}}` placeholder in templates.json that can't be filled in
+    #[error("{0}")]
+    InvalidTemplate(String),
+}
+
+pub type Result<T, E = ConfigError> = std::result::Result<T, E>;
+
+/// Attaches a description to an I/O failure, like `anyhow::Context`.
+pub(crate) trait IoContext<T> {
+    fn io_context(self, context: impl FnOnce() -> String) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context(self, context: impl FnOnce() -> String) -> Result<T> {
+        self.map_err(|source| ConfigError::Io {
+            context: context(),
+            source,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Profile {
+    #[schemars(length(min = 1, max = MAX_PROFILE_NAME_LEN), regex(pattern = r"^[A-Za-z0-9._-]+$"))]
     pub name: String,
     pub backend: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Free-form annotations, which survive saves where JSON comments wouldn't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+    /// The access token is in the OS keyring under the profile's name rather
+    /// than in `access_token` (see `--set-token`).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub access_token_in_keyring: bool,
+    /// Path to a file holding the stack passphrase; the passphrase itself is never stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_file: Option<PathBuf>,
+    /// Unix seconds of the last activation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<i64>,
+    /// Listed above every other profile in the selector; at most one is pinned.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Activated by `--current` when no profile is named; at most one is the default.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub default: bool,
+    /// A `ProfileGroup` whose base `backend` is relative to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Extra variables exported on activation, such as `AWS_REGION`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+/// A backend prefix shared by several profiles, such as `s3://company-state/`.
+/// Member profiles store only the part after it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ProfileGroup {
+    pub name: String,
+    pub base: String,
 }
 
 impl Profile {
     pub fn new(name: String, backend: String) -> Self {
-        Self { name, backend }
+        Self {
+            name,
+            backend,
+            description: None,
+            notes: None,
+            tags: Vec::new(),
+            access_token: None,
+            access_token_in_keyring: false,
+            passphrase_file: None,
+            last_used: None,
+            pinned: false,
+            default: false,
+            group: None,
+            env: BTreeMap::new(),
+        }
+    }
+
+    /// The backend to activate. For a member of one of `groups`, `backend` is
+    /// appended to the group's base; a backend with its own scheme, or one
+    /// naming an unknown group, is used as is.
+    pub fn full_backend(&self, groups: &[ProfileGroup]) -> String {
+        let base = self
+            .group
+            .as_ref()
+            .and_then(|name| groups.iter().find(|g| &g.name == name))
+            .map(|g| g.base.as_str());
+
+        match base {
+            Some(base) if !self.backend.contains("://") => {
+                let suffix = self.backend.trim_start_matches('/');
+                if suffix.is_empty() {
+                    base.to_string()
+                } else {
+                    format!("{}/{}", base.trim_end_matches('/'), suffix)
+                }
+            }
+            _ => self.backend.clone(),
+        }
+    }
+
+    /// Pulumi Cloud (and self-hosted service) backends are the only ones that
+    /// authenticate with an access token.
+    pub fn is_cloud_backend(&self) -> bool {
+        self.backend.starts_with("https://") || self.backend.starts_with("http://")
+    }
+
+    /// Environment variables to export when this profile is activated. Extra
+    /// variables from `env` follow the managed ones; entries rejected by
+    /// `check_extra_env_var` are left out.
+    pub fn env_vars(&self) -> Vec<(&str, String)> {
+        let mut vars = vec![("PULUMI_BACKEND_URL", self.backend.clone())];
+
+        if self.is_cloud_backend()
+            && let Some(token) = &self.access_token
+        {
+            vars.push(("PULUMI_ACCESS_TOKEN", token.clone()));
+        }
+
+        // Self-managed backends encrypt secrets with a passphrase instead
+        if !self.is_cloud_backend()
+            && let Some(path) = &self.passphrase_file
+        {
+            vars.push(("PULUMI_CONFIG_PASSPHRASE_FILE", path.display().to_string()));
+        }
+
+        for (name, value) in &self.env {
+            if check_extra_env_var(name, value).is_ok() {
+                vars.push((name.as_str(), value.clone()));
+            }
+        }
+
+        vars
+    }
+
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|tag| self.tags.contains(tag))
+    }
+
+    /// Whether the name or backend contains `query`, ignoring case.
+    pub fn matches_query(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.name.to_lowercase().contains(&query) || self.backend.to_lowercase().contains(&query)
+    }
+}
+
+/// The directory a `file://` backend stores state in: `file:///abs/path` is
+/// absolute, `file://./state` relative to the working directory, and
+/// `file://~` the home directory. `None` for other schemes.
+pub fn file_backend_path(url: &str) -> Option<PathBuf> {
+    let location = url.strip_prefix("file://")?;
+    let location = location.split(['?', '#']).next().unwrap_or_default();
+
+    match location {
+        "" => None,
+        "~" => dirs::home_dir(),
+        _ => match location.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+            None => Some(PathBuf::from(location)),
+        },
+    }
+}
+
+/// Makes a relative `file://` backend such as `file://./state` absolute,
+/// against `cwd`, so it names the same directory wherever Pulumi later runs.
+/// An existing directory is canonicalized, resolving symlinks; one that
+/// doesn't exist yet only has its `.` and `..` components folded away. Any
+/// `?query` is kept. Absolute, `~` and non-file backends are returned as is.
+pub fn resolve_file_backend(url: &str, cwd: &Path) -> Result<String> {
+    let Some(location) = url.strip_prefix("file://") else {
+        return Ok(url.to_string());
+    };
+    let end = location.find(['?', '#']).unwrap_or(location.len());
+    let (path, suffix) = location.split_at(end);
+    if path.is_empty() || path.starts_with('~') || Path::new(path).is_absolute() {
+        return Ok(url.to_string());
+    }
+
+    let joined = cwd.join(path);
+    let resolved = match fs::canonicalize(&joined) {
+        Ok(resolved) => resolved,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut folded = PathBuf::new();
+            for component in joined.components() {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        folded.pop();
+                    }
+                    other => folded.push(other),
+                }
+            }
+            folded
+        }
+        Err(e) => return Err(e).io_context(|| format!("Failed to resolve backend directory {joined:?}")),
+    };
+
+    Ok(format!("file://{}{}", resolved.display(), suffix))
+}
+
+/// Substitutes `$VAR` and `${VAR}` in a backend URL from the environment, so
+/// profiles can store templates such as `s3://$STATE_BUCKET/infra`.
+pub fn expand_backend(url: &str) -> Result<String> {
+    expand_backend_with(url, |name| env::var(name).ok())
+}
+
+/// Whether `PULUMI_BACKEND_URL` in the environment, if set, names `profile`'s
+/// backend. Pulumi prefers the variable over the active profile, so a stale
+/// export from an earlier activation silently wins.
+pub fn env_backend_matches(profile: &Profile) -> bool {
+    backend_matches_env(profile, env::var("PULUMI_BACKEND_URL").ok().as_deref())
+}
+
+fn backend_matches_env(profile: &Profile, env_value: Option<&str>) -> bool {
+    let Some(env_value) = env_value.filter(|value| !value.is_empty()) else {
+        return true;
+    };
+    // A template that can't be expanded is compared as written
+    let backend = expand_backend(&profile.backend).unwrap_or_else(|_| profile.backend.clone());
+    normalize_backend(env_value) == normalize_backend(&backend)
+}
+
+fn expand_backend_with(url: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut expanded = String::with_capacity(url.len());
+    let mut rest = url;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        let after = &rest[pos + 1..];
+
+        let (name, remainder) = if let Some(braced) = after.strip_prefix('{') {
+            let end = braced.find('}').ok_or_else(|| {
+                ConfigError::InvalidBackend(format!("Unterminated '${{' in backend URL '{}'", url))
+            })?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = after.find(|c: char| !is_name_char(c)).unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+
+        // A `$` not followed by a variable name is kept as-is
+        let is_valid_name = name.chars().all(is_name_char)
+            && name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+        if !is_valid_name {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        let value = lookup(name).ok_or_else(|| ConfigError::UnsetVariable {
+            url: url.to_string(),
+            name: name.to_string(),
+        })?;
+        expanded.push_str(&value);
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// Names accepted by `--sort` and the `sort` setting, in `SortKey` order.
+pub const SORT_KEYS: &[&str] = &["name", "backend", "recent", "none"];
+
+/// How `--list` and the selector order profiles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    /// Alphabetically, ignoring case
+    #[default]
+    Name,
+    /// By backend URL, then name
+    Backend,
+    /// Most recently activated first, never-used profiles last
+    Recent,
+    /// profiles.json order
+    None,
+}
+
+impl SortKey {
+    /// The key called `name` in `SORT_KEYS`.
+    pub fn from_name(name: &str) -> Option<SortKey> {
+        match name {
+            "name" => Some(SortKey::Name),
+            "backend" => Some(SortKey::Backend),
+            "recent" => Some(SortKey::Recent),
+            "none" => Some(SortKey::None),
+            _ => None,
+        }
+    }
+
+    /// Compares two profiles by this key. Ties fall back to the name, except
+    /// for `None`, where everything ties so a stable sort keeps file order.
+    pub fn compare(self, a: &Profile, b: &Profile) -> std::cmp::Ordering {
+        let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.name.cmp(&b.name));
+        match self {
+            SortKey::Name => by_name(),
+            SortKey::Backend => a.backend.cmp(&b.backend).then_with(by_name),
+            SortKey::Recent => b.last_used.cmp(&a.last_used).then_with(by_name),
+            SortKey::None => std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+/// Orders profiles by `key`, shared by `--list` and the selector.
+pub fn sort_profiles(profiles: &mut [Profile], key: SortKey) {
+    profiles.sort_by(|a, b| key.compare(a, b));
+}
+
+/// Orders profiles by most recent activation, never-used profiles last, with
+/// ties broken by name so the order is deterministic.
+pub fn sort_by_recent(profiles: &mut [Profile]) {
+    sort_profiles(profiles, SortKey::Recent);
+}
+
+/// The scheme of a backend URL, such as `s3` for `s3://bucket`, or `other`
+/// when the URL has none.
+pub fn backend_scheme(backend: &str) -> &str {
+    backend.split_once("://").map_or("other", |(scheme, _)| scheme)
+}
+
+/// Hosts (buckets, containers, servers) of the existing backends using
+/// `scheme`, most recently used first and without duplicates, for completing
+/// the guided add prompt.
+pub fn backend_suggestions(profiles: &[Profile], scheme: &str) -> Vec<String> {
+    let mut recent: Vec<&Profile> = profiles.iter().collect();
+    // Stable, so never-used profiles keep their file order
+    recent.sort_by_key(|p| std::cmp::Reverse(p.last_used));
+
+    let mut hosts: Vec<String> = Vec::new();
+    for profile in recent {
+        let Some(rest) = profile.backend.strip_prefix(scheme).and_then(|rest| rest.strip_prefix("://")) else {
+            continue;
+        };
+        let host = rest.split(['/', '?']).next().unwrap_or_default();
+        if !host.is_empty() && !hosts.iter().any(|h| h == host) {
+            hosts.push(host.to_string());
+        }
+    }
+
+    hosts
+}
+
+/// Splits profiles into groups sharing a backend scheme. Groups appear in the
+/// order their first member does, and members keep their relative order.
+pub fn group_by_scheme(profiles: Vec<Profile>) -> Vec<(String, Vec<Profile>)> {
+    let mut groups: Vec<(String, Vec<Profile>)> = Vec::new();
+    for profile in profiles {
+        let scheme = backend_scheme(&profile.backend);
+        match groups.iter_mut().find(|(name, _)| name == scheme) {
+            Some((_, members)) => members.push(profile),
+            None => groups.push((scheme.to_string(), vec![profile])),
+        }
+    }
+
+    groups
+}
+
+/// The profile activated most recently, if any has ever been used.
+pub fn most_recent_profile(profiles: &[Profile]) -> Option<&Profile> {
+    profiles
+        .iter()
+        .filter(|p| p.last_used.is_some())
+        .max_by_key(|p| p.last_used)
+}
+
+/// The profile after the one named `current`, wrapping around from the last
+/// to the first. When `current` is `None` or names none of `profiles`, the
+/// first profile is returned.
+///
+/// # Panics
+///
+/// If `profiles` is empty.
+pub fn next_profile<'a>(profiles: &'a [Profile], current: Option<&str>) -> &'a Profile {
+    rotate_profile(profiles, current, 1)
+}
+
+/// Like `next_profile`, but steps back, wrapping from the first to the last.
+///
+/// # Panics
+///
+/// If `profiles` is empty.
+pub fn previous_profile<'a>(profiles: &'a [Profile], current: Option<&str>) -> &'a Profile {
+    rotate_profile(profiles, current, profiles.len() - 1)
+}
+
+fn rotate_profile<'a>(profiles: &'a [Profile], current: Option<&str>, step: usize) -> &'a Profile {
+    match current.and_then(|name| profiles.iter().position(|p| p.name == name)) {
+        Some(index) => &profiles[(index + step) % profiles.len()],
+        None => &profiles[0],
+    }
+}
+
+/// How a name typed on the command line matched the profiles.
+#[derive(Debug, PartialEq)]
+pub enum Resolution<'a> {
+    /// A profile has exactly this name
+    Exact(&'a Profile),
+    /// No exact match, but exactly one name starts with the query, ignoring case
+    UniquePrefix(&'a Profile),
+    /// Several names start with the query
+    Ambiguous(Vec<&'a Profile>),
+    NotFound,
+}
+
+/// Finds the profile named `query`, falling back to a unique case-insensitive
+/// prefix match so `prod` can stand in for `production`.
+pub fn resolve_profile<'a>(profiles: &'a [Profile], query: &str) -> Resolution<'a> {
+    if let Some(profile) = profiles.iter().find(|p| p.name == query) {
+        return Resolution::Exact(profile);
+    }
+
+    let query = query.to_lowercase();
+    let mut candidates: Vec<&Profile> = profiles
+        .iter()
+        .filter(|p| p.name.to_lowercase().starts_with(&query))
+        .collect();
+
+    match candidates.len() {
+        0 => Resolution::NotFound,
+        1 => Resolution::UniquePrefix(candidates.remove(0)),
+        _ => Resolution::Ambiguous(candidates),
+    }
+}
+
+/// Orders profiles by `sort` and numbers them from 1, top to bottom. `--list`
+/// shows these numbers and `--activate-index` counts with them.
+pub fn ordered_profiles(profiles: &[Profile], sort: SortKey) -> Vec<(usize, &Profile)> {
+    let mut sorted: Vec<&Profile> = profiles.iter().collect();
+    sorted.sort_by(|a, b| sort.compare(a, b));
+    sorted.into_iter().enumerate().map(|(i, profile)| (i + 1, profile)).collect()
+}
+
+/// The profile `--list` shows as number `index` under `sort`, or `None` when
+/// out of range.
+pub fn profile_at_index(profiles: &[Profile], sort: SortKey, index: usize) -> Option<&Profile> {
+    ordered_profiles(profiles, sort)
+        .into_iter()
+        .find(|(i, _)| *i == index)
+        .map(|(_, profile)| profile)
+}
+
+/// Keeps the profiles `keep` accepts, in their original order. `--tag` and
+/// `--filter` narrow the selector's options through it.
+pub fn filter_profiles(profiles: Vec<Profile>, keep: impl Fn(&Profile) -> bool) -> Vec<Profile> {
+    profiles.into_iter().filter(|p| keep(p)).collect()
+}
+
+/// Keeps only the profiles carrying every one of `tags`.
+pub fn filter_by_tags(profiles: Vec<Profile>, tags: &[String]) -> Vec<Profile> {
+    filter_profiles(profiles, |p| p.has_tags(tags))
+}
+
+/// Keeps the profiles whose name or backend contains `query`, ignoring case.
+pub fn filter_by_query(profiles: Vec<Profile>, query: &str) -> Vec<Profile> {
+    filter_profiles(profiles, |p| p.matches_query(query))
+}
+
+/// Whether the whole of `name` matches `pattern`, ignoring case: `*` stands
+/// for any run of characters (including none), `?` for exactly one, and
+/// everything else for itself. Unlike `--filter`, a pattern without
+/// wildcards only matches the name it spells out.
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` when the rest fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the `*` swallow one more character
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    n = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Keeps the profiles whose name matches the glob `pattern` (see `matches_glob`).
+pub fn filter_by_glob(profiles: Vec<Profile>, pattern: &str) -> Vec<Profile> {
+    filter_profiles(profiles, |p| matches_glob(pattern, &p.name))
+}
+
+/// Profiles whose backend contains `needle`, ignoring case.
+pub fn find_by_backend<'a>(profiles: &'a [Profile], needle: &str) -> Vec<&'a Profile> {
+    let needle = needle.to_lowercase();
+    profiles
+        .iter()
+        .filter(|p| p.backend.to_lowercase().contains(&needle))
+        .collect()
+}
+
+/// Environment variable holding profiles JSON that replaces the profiles file,
+/// for stateless use in containers and CI.
+pub const PROFILES_JSON_ENV: &str = "PULUMI_PROFILES_JSON";
+
+/// Where the profiles are read from. JSON from `PULUMI_PROFILES_JSON` or
+/// `--profiles-url` replaces the file entirely and can't be changed, so only
+/// a `File` source has a path to write to or put an overlay next to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfilesSource {
+    File(PathBuf),
+    /// The JSON in `PULUMI_PROFILES_JSON`
+    Inline(String),
+    /// The JSON fetched from `url` by `--profiles-url`, held in memory only
+    Remote { url: String, content: String },
+}
+
+impl ProfilesSource {
+    /// The source in effect without `--profiles-url`: the JSON in a non-empty
+    /// `PULUMI_PROFILES_JSON`, otherwise the file `file` returns.
+    pub fn resolve(file: impl FnOnce() -> Result<PathBuf>) -> Result<ProfilesSource> {
+        match env::var(PROFILES_JSON_ENV) {
+            Ok(json) if !json.is_empty() => Ok(ProfilesSource::Inline(json)),
+            _ => file().map(ProfilesSource::File),
+        }
+    }
+
+    /// The profiles file, when the profiles come from one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ProfilesSource::File(path) => Some(path),
+            ProfilesSource::Inline(_) | ProfilesSource::Remote { .. } => None,
+        }
+    }
+
+    /// The profiles file to change, or the error explaining why this source
+    /// can't be changed.
+    pub fn writable_path(&self) -> Result<&Path> {
+        match self {
+            ProfilesSource::File(path) => Ok(path),
+            ProfilesSource::Inline(_) => Err(ConfigError::ReadOnly),
+            ProfilesSource::Remote { url, .. } => Err(ConfigError::RemoteReadOnly(url.clone())),
+        }
+    }
+
+    /// The profiles with the local overlay merged in, like `load_merged_profiles`.
+    pub fn load(&self) -> Result<Vec<Profile>> {
+        match self {
+            ProfilesSource::File(path) => load_merged_profiles(path),
+            ProfilesSource::Inline(json) | ProfilesSource::Remote { content: json, .. } => {
+                log::debug!("Reading profiles from {self}");
+                parse_profiles(json).map_err(|e| self.invalid(e))
+            }
+        }
+    }
+
+    /// Like `load`, but first creates an empty profiles file if there is none.
+    pub fn read(&self) -> Result<Vec<Profile>> {
+        match self {
+            ProfilesSource::File(path) => read_pulumi_profiles(path),
+            ProfilesSource::Inline(_) | ProfilesSource::Remote { .. } => self.load(),
+        }
+    }
+
+    /// The groups defined alongside the profiles.
+    pub fn load_groups(&self) -> Result<Vec<ProfileGroup>> {
+        match self {
+            ProfilesSource::File(path) => load_profile_groups(path),
+            ProfilesSource::Inline(json) | ProfilesSource::Remote { content: json, .. } => {
+                parse_profiles_file(json).map(|file| file.groups).map_err(|e| self.invalid(e))
+            }
+        }
+    }
+
+    /// Names the variable or URL in an error about JSON that doesn't parse.
+    fn invalid(&self, error: ConfigError) -> ConfigError {
+        match self {
+            ProfilesSource::Remote { url, .. } => ConfigError::InvalidRemote(url.clone(), Box::new(error)),
+            ProfilesSource::File(_) | ProfilesSource::Inline(_) => ConfigError::InvalidInline(Box::new(error)),
+        }
     }
 }
 
-pub fn read_pulumi_profiles() -> Result<Vec<Profile>> {
-    let profiles_path = get_pulumi_profiles_path()?;
+impl fmt::Display for ProfilesSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfilesSource::File(path) => write!(f, "{}", path.display()),
+            ProfilesSource::Inline(_) => f.write_str(PROFILES_JSON_ENV),
+            ProfilesSource::Remote { url, .. } => f.write_str(url),
+        }
+    }
+}
 
+/// Reads the profiles, first creating an empty profiles file if there is none.
+pub fn read_pulumi_profiles(profiles_path: &Path) -> Result<Vec<Profile>> {
     if !profiles_path.exists() {
         // Create empty profiles file if it doesn't exist
-        let empty_profiles: Vec<Profile> = Vec::new();
-        save_pulumi_profiles(&empty_profiles)?;
-        return Ok(empty_profiles);
+        save_pulumi_profiles(profiles_path, &[])?;
+    }
+    load_merged_profiles(profiles_path)
+}
+
+/// Names that appear more than once, each listed once in order of first appearance.
+pub fn find_duplicate_names(profiles: &[Profile]) -> Vec<String> {
+    let mut duplicates: Vec<String> = Vec::new();
+    for (i, profile) in profiles.iter().enumerate() {
+        if profiles[..i].iter().any(|p| p.name == profile.name) && !duplicates.contains(&profile.name) {
+            duplicates.push(profile.name.clone());
+        }
+    }
+    duplicates
+}
+
+/// Reads the profiles without touching the disk; a missing file has none.
+pub fn load_pulumi_profiles(profiles_path: &Path) -> Result<Vec<Profile>> {
+    if !profiles_path.exists() {
+        log::debug!("No profiles file at {}", profiles_path.display());
+        return Ok(Vec::new());
+    }
+
+    read_profiles_file(profiles_path)
+}
+
+/// The optional overlay next to a profiles file: `profiles.local.json` for
+/// `profiles.json`. It is meant to stay out of version control.
+pub fn get_local_profiles_path(profiles_path: &Path) -> PathBuf {
+    let stem = profiles_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "profiles".to_string());
+    profiles_path.with_file_name(format!("{stem}.local.json"))
+}
+
+/// Applies `overlay` on top of `base`: a profile with the same name replaces
+/// the base entry in place, and the rest are appended in order.
+pub fn merge_profiles(mut base: Vec<Profile>, overlay: Vec<Profile>) -> Vec<Profile> {
+    for profile in overlay {
+        match base.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => base.push(profile),
+        }
+    }
+    base
+}
+
+/// Like `load_pulumi_profiles`, with the local overlay (see
+/// `get_local_profiles_path`) merged in when it exists. Writes go through
+/// `update_profiles`, which sees only the one file it saves.
+pub fn load_merged_profiles(profiles_path: &Path) -> Result<Vec<Profile>> {
+    let profiles = load_pulumi_profiles(profiles_path)?;
+    let local_path = get_local_profiles_path(profiles_path);
+    if !local_path.exists() {
+        return Ok(profiles);
+    }
+    Ok(merge_profiles(profiles, read_profiles_file(&local_path)?))
+}
+
+/// Fails with `OnlyInOverlay` when `name` is missing from the profiles file
+/// but defined by its local overlay. Changes made through `update_profiles`
+/// see only the base file, so they'd otherwise report a listed profile as not
+/// found.
+pub fn check_not_overlay_only(profiles_path: &Path, name: &str) -> Result<()> {
+    let local_path = get_local_profiles_path(profiles_path);
+    if local_path.exists()
+        && !load_pulumi_profiles(profiles_path)?.iter().any(|p| p.name == name)
+        && read_profiles_file(&local_path)?.iter().any(|p| p.name == name)
+    {
+        return Err(ConfigError::OnlyInOverlay(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Parses a profiles file, failing if it doesn't exist.
+fn read_profiles_file(profiles_path: &Path) -> Result<Vec<Profile>> {
+    log::debug!("Reading profiles from {}", profiles_path.display());
+    let content = fs::read_to_string(profiles_path)
+        .io_context(|| format!("Failed to read Pulumi profiles file: {profiles_path:?}"))?;
+
+    parse_profiles(&content)
+}
+
+/// Version written to disk by `save_pulumi_profiles`.
+pub const PROFILES_FILE_VERSION: u32 = 1;
+
+/// On-disk layout of the profiles file.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ProfilesFile {
+    pub version: u32,
+    pub profiles: Vec<Profile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<ProfileGroup>,
+}
+
+/// JSON Schema for the current profiles file layout, for validating a
+/// committed profiles.json in CI. Keys are sorted, so the output is stable.
+pub fn profiles_json_schema() -> String {
+    let schema = schemars::schema_for!(ProfilesFile);
+    serde_json::to_string_pretty(&schema).expect("schemas serialize to JSON")
+}
+
+fn parse_profiles(content: &str) -> Result<Vec<Profile>> {
+    parse_profiles_file(content).map(|file| file.profiles)
+}
+
+fn parse_profiles_file(content: &str) -> Result<ProfilesFile> {
+    let value: Value = serde_json::from_str(content).map_err(|source| ConfigError::Parse {
+        context: format!(
+            "Failed to parse Pulumi profiles JSON at line {}, column {}. \
+             Run with --repair to back up the file and start with an empty list",
+            source.line(),
+            source.column()
+        ),
+        source,
+    })?;
+
+    // Files predating the version field are version 0
+    let version = match value.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| ConfigError::InvalidFile(format!("Invalid profiles file version: {version}")))?,
+    };
+
+    match migrate(version, value.clone()) {
+        Ok(file) => Ok(file),
+        Err(e @ ConfigError::UnsupportedVersion(_)) => Err(e),
+        // A hand-written file often holds one profile without the surrounding list
+        Err(_) if serde_json::from_value::<Profile>(value).is_ok() => Err(ConfigError::InvalidFile(
+            "Failed to parse Pulumi profiles JSON: the file holds a single profile object instead of a list. \
+             Run with --repair to wrap it in a list"
+                .to_string(),
+        )),
+        Err(ConfigError::Parse { source, .. }) => Err(ConfigError::Parse {
+            context: "Failed to parse Pulumi profiles JSON. \
+                      Run with --repair to back up the file and start with an empty list"
+                .to_string(),
+            source,
+        }),
+        Err(e) => Err(ConfigError::InvalidFile(format!(
+            "Failed to parse Pulumi profiles JSON: {e}. \
+             Run with --repair to back up the file and start with an empty list"
+        ))),
+    }
+}
+
+/// Upgrades a profiles file of version `from` to `PROFILES_FILE_VERSION`, one
+/// version at a time. A format change adds a step here and bumps the constant.
+pub fn migrate(from: u32, value: Value) -> Result<ProfilesFile> {
+    if from > PROFILES_FILE_VERSION {
+        return Err(ConfigError::UnsupportedVersion(from));
+    }
+
+    let mut value = value;
+    for version in from..PROFILES_FILE_VERSION {
+        value = match version {
+            0 => migrate_v0(value)?,
+            _ => unreachable!("no migration from profiles file version {version}"),
+        };
+    }
+
+    serde_json::from_value(value).map_err(|source| ConfigError::Parse {
+        context: "Failed to parse Pulumi profiles JSON".to_string(),
+        source,
+    })
+}
+
+/// Version 0 was a bare array, or a hand-edited `{ "profiles": [...] }` wrapper.
+fn migrate_v0(value: Value) -> Result<Value> {
+    let profiles = match value {
+        Value::Array(_) => value,
+        Value::Object(mut map) if map.contains_key("profiles") => map.remove("profiles").unwrap_or_default(),
+        _ => return Err(ConfigError::InvalidFile("expected a list of profiles".to_string())),
+    };
+
+    Ok(serde_json::json!({ "version": 1, "profiles": profiles }))
+}
+
+/// What `repair_profiles` did to the profiles file.
+#[derive(Debug, PartialEq)]
+pub enum RepairOutcome {
+    /// The file already parsed; nothing was changed
+    Healthy,
+    /// A lone profile object was wrapped in an array
+    Wrapped { backup: PathBuf },
+    /// The unreadable file was replaced with an empty array
+    Reset { backup: PathBuf },
+}
+
+/// Fixes a profiles file that no longer parses, first copying the broken
+/// contents to `profiles.json.bak`.
+pub fn repair_profiles(profiles_path: &Path) -> Result<RepairOutcome> {
+    let _lock = lock_profiles(profiles_path)?;
+
+    let content = match fs::read_to_string(profiles_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(RepairOutcome::Healthy),
+        Err(e) => {
+            return Err(e)
+                .io_context(|| format!("Failed to read Pulumi profiles file: {profiles_path:?}"));
+        }
+    };
+    if parse_profiles(&content).is_ok() {
+        return Ok(RepairOutcome::Healthy);
+    }
+
+    let backup = backup_path(profiles_path, 0);
+    log::debug!("Backing up {} to {}", profiles_path.display(), backup.display());
+    fs::copy(profiles_path, &backup)
+        .io_context(|| format!("Failed to back up profiles file to {backup:?}"))?;
+
+    match serde_json::from_str::<Profile>(&content) {
+        Ok(profile) => {
+            save_pulumi_profiles(profiles_path, &[profile])?;
+            Ok(RepairOutcome::Wrapped { backup })
+        }
+        Err(_) => {
+            save_pulumi_profiles(profiles_path, &[])?;
+            Ok(RepairOutcome::Reset { backup })
+        }
     }
+}
 
-    let content = fs::read_to_string(&profiles_path)
-        .with_context(|| format!("Failed to read Pulumi profiles file: {profiles_path:?}"))?;
+/// Reads the groups defined alongside the profiles; a missing file has none.
+pub fn load_profile_groups(profiles_path: &Path) -> Result<Vec<ProfileGroup>> {
+    if !profiles_path.exists() {
+        return Ok(Vec::new());
+    }
 
-    let profiles: Vec<Profile> = serde_json::from_str(&content)
-        .with_context(|| "Failed to parse Pulumi profiles JSON")?;
+    let content = fs::read_to_string(profiles_path)
+        .io_context(|| format!("Failed to read Pulumi profiles file: {profiles_path:?}"))?;
+    parse_profiles_file(&content).map(|file| file.groups)
+}
 
-    Ok(profiles)
+/// Replaces each profile's backend with its `full_backend`, for showing and
+/// activating profiles. The result must not be saved back.
+pub fn resolve_group_backends(profiles: Vec<Profile>, groups: &[ProfileGroup]) -> Vec<Profile> {
+    profiles
+        .into_iter()
+        .map(|p| Profile {
+            backend: p.full_backend(groups),
+            ..p
+        })
+        .collect()
 }
 
-pub fn save_pulumi_profiles(profiles: &[Profile]) -> Result<()> {
-    let profiles_path = get_pulumi_profiles_path()?;
+/// Writes `profiles`, keeping whatever groups the file already defines.
+pub fn save_pulumi_profiles(profiles_path: &Path, profiles: &[Profile]) -> Result<()> {
+    // Groups are only edited by hand, and an unreadable file has none worth keeping
+    let groups = load_profile_groups(profiles_path).unwrap_or_default();
+    write_profiles_file(profiles_path, profiles, groups)
+}
 
+fn write_profiles_file(profiles_path: &Path, profiles: &[Profile], groups: Vec<ProfileGroup>) -> Result<()> {
     // Create .pulumi directory if it doesn't exist
     if let Some(parent) = profiles_path.parent() {
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
     }
 
-    let content = serde_json::to_string_pretty(profiles)
-        .with_context(|| "Failed to serialize profiles to JSON")?;
+    let file = ProfilesFile {
+        version: PROFILES_FILE_VERSION,
+        profiles: profiles.to_vec(),
+        groups,
+    };
+    let content = serde_json::to_string_pretty(&file).map_err(|source| ConfigError::Parse {
+        context: "Failed to serialize profiles to JSON".to_string(),
+        source,
+    })?;
+
+    log::debug!("Writing {} profile(s) to {}", profiles.len(), profiles_path.display());
+    write_atomic(profiles_path, content.as_bytes())
+        .io_context(|| format!("Failed to write Pulumi profiles file: {profiles_path:?}"))?;
+
+    Ok(())
+}
+
+/// Writes `contents` to a sibling `.tmp` file and renames it over `path`, so
+/// readers never observe a truncated file. Existing permissions are kept.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    fs::write(&tmp_path, contents)?;
 
-    fs::write(&profiles_path, content)
-        .with_context(|| format!("Failed to write Pulumi profiles file: {profiles_path:?}"))?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(&tmp_path, metadata.permissions())?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
 
     Ok(())
 }
 
-pub fn add_profile(name: String, backend: String) -> Result<()> {
-    let mut profiles = read_pulumi_profiles()?;
-    
-    // Check if profile already exists
-    if profiles.iter().any(|p| p.name == name) {
-        return Err(anyhow::anyhow!("Profile '{}' already exists", name));
+/// Drops a single trailing slash, so `s3://bucket/path/` and `s3://bucket/path`
+/// are stored alike. A location that is only `/`, as in `file:///`, is kept.
+pub fn normalize_backend(url: &str) -> String {
+    let location = url.split_once("://").map_or(url, |(_, rest)| rest);
+    match url.strip_suffix('/') {
+        Some(stripped) if location.len() > 1 => stripped.to_string(),
+        _ => url.to_string(),
+    }
+}
+
+pub fn validate_backend_url(url: &str) -> Result<()> {
+    let allowed = || {
+        BACKEND_SCHEMES
+            .iter()
+            .map(|scheme| format!("{scheme}://"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return Err(ConfigError::InvalidBackend(format!(
+            "Invalid backend URL '{}': expected <scheme>://<location> using one of: {}",
+            url,
+            allowed()
+        )));
+    };
+
+    if !BACKEND_SCHEMES.contains(&scheme) {
+        return Err(ConfigError::InvalidBackend(format!(
+            "Unsupported backend scheme '{}' in '{}': allowed schemes are {}",
+            scheme,
+            url,
+            allowed()
+        )));
     }
 
-    profiles.push(Profile::new(name, backend));
-    save_pulumi_profiles(&profiles)?;
+    if rest.is_empty() || rest.chars().any(char::is_whitespace) {
+        return Err(ConfigError::InvalidBackend(format!(
+            "Invalid backend URL '{}': missing or malformed location after '{}://'",
+            url, scheme
+        )));
+    }
 
     Ok(())
 }
 
-pub fn edit_profile(name: &str, new_backend: String) -> Result<()> {
-    let mut profiles = read_pulumi_profiles()?;
-    
-    // Find and update the profile
-    if let Some(profile) = profiles.iter_mut().find(|p| p.name == name) {
-        profile.backend = new_backend;
-        save_pulumi_profiles(&profiles)?;
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!("Profile '{}' not found", name))
+fn is_profile_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+/// Profile names end up in shell output and completion scripts, so keep them
+/// to a conservative ASCII set.
+pub fn validate_profile_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(ConfigError::InvalidName("Profile name cannot be empty".to_string()));
+    }
+
+    if let Some(bad) = name.chars().find(|c| !is_profile_name_char(*c)) {
+        return Err(ConfigError::InvalidName(format!(
+            "Invalid profile name '{}': character {:?} is not allowed (use letters, digits, '.', '_' or '-')",
+            name.escape_default(),
+            bad
+        )));
+    }
+
+    if name.len() > MAX_PROFILE_NAME_LEN {
+        return Err(ConfigError::InvalidName(format!(
+            "Invalid profile name '{}': longer than {} characters",
+            name, MAX_PROFILE_NAME_LEN
+        )));
     }
+
+    Ok(())
 }
 
-pub fn delete_profile(name: &str) -> Result<()> {
-    let mut profiles = read_pulumi_profiles()?;
-    
-    let original_len = profiles.len();
-    profiles.retain(|p| p.name != name);
-    
-    if profiles.len() == original_len {
-        return Err(anyhow::anyhow!("Profile '{}' not found", name));
+/// Extra variables are interpolated into shell commands, so names must be
+/// plain identifiers outside `MANAGED_ENV_VARS` and `RESERVED_ENV_VARS`, and
+/// values may not contain characters that escape double quotes.
+pub fn check_extra_env_var(name: &str, value: &str) -> Result<()> {
+    let is_identifier = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(ConfigError::InvalidEnvVar(format!(
+            "Invalid environment variable name '{}' (use letters, digits and '_')",
+            name.escape_default()
+        )));
+    }
+
+    // Windows compares variable names case-insensitively
+    let upper = name.to_ascii_uppercase();
+    if MANAGED_ENV_VARS.contains(&upper.as_str())
+        || RESERVED_ENV_VARS.contains(&upper.as_str())
+        || RESERVED_ENV_PREFIXES.iter().any(|prefix| upper.starts_with(prefix))
+    {
+        return Err(ConfigError::InvalidEnvVar(format!("Environment variable {name} is reserved")));
+    }
+
+    if let Some(bad) = value.chars().find(|c| matches!(c, '"' | '$' | '`' | '\\' | '\n' | '\r')) {
+        return Err(ConfigError::InvalidEnvVar(format!(
+            "Value of environment variable {name}: character {bad:?} is not allowed"
+        )));
     }
 
-    save_pulumi_profiles(&profiles)?;
     Ok(())
 }
 
-fn get_pulumi_profiles_path() -> Result<PathBuf> {
-    let home_dir =
-        dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Unable to determine home directory"))?;
+/// Names of every extra variable any of `profiles` sets, sorted and without
+/// duplicates, so deactivation can unset whichever one was active.
+pub fn extra_env_var_names(profiles: &[Profile]) -> Vec<&str> {
+    let mut names: Vec<&str> = profiles
+        .iter()
+        .flat_map(|p| p.env.keys().map(String::as_str))
+        .filter(|name| check_extra_env_var(name, "").is_ok())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+/// Runs a read-modify-write of the profiles file while holding an exclusive
+/// advisory lock, so concurrent invocations can't clobber each other's changes.
+/// The profiles are only saved when `update` succeeds.
+/// Before saving, the previous file is kept as a backup (see `backup_profiles`).
+pub fn update_profiles<T>(
+    profiles_path: &Path,
+    options: &WriteOptions,
+    update: impl FnOnce(&mut Vec<Profile>) -> Result<T>,
+) -> Result<T> {
+    if options.dry_run {
+        // Without a write there's nothing to lock or back up
+        return update(&mut load_pulumi_profiles(profiles_path)?);
+    }
+    let _lock = lock_profiles(profiles_path)?;
+
+    // A file created by this very read has nothing worth backing up
+    let existed = profiles_path.exists();
+    let mut profiles = load_pulumi_profiles(profiles_path)?;
+    let result = update(&mut profiles)?;
+    if existed {
+        backup_profiles(profiles_path, options.backups)?;
+    }
+    save_pulumi_profiles(profiles_path, &profiles)?;
+
+    Ok(result)
+}
+
+/// Number of backups kept when `--backups` isn't given.
+pub const DEFAULT_BACKUPS: usize = 1;
 
-    Ok(home_dir.join(".pulumi").join("profiles.json"))
+/// How `update_profiles`, and every change made through it, writes the
+/// profiles file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Backups kept by `backup_profiles`; 0 disables them
+    pub backups: usize,
+    /// Validate and apply changes in memory only, never writing the profiles
+    /// file or its backups
+    pub dry_run: bool,
+    /// Let names differing only in case, such as `Prod` and `prod`, coexist
+    pub case_sensitive: bool,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            backups: DEFAULT_BACKUPS,
+            dry_run: false,
+            case_sensitive: false,
+        }
+    }
+}
 
-    #[test]
-    fn test_profile_creation() {
-        let profile = Profile::new("test".to_string(), "file://./state".to_string());
-        assert_eq!(profile.name, "test");
-        assert_eq!(profile.backend, "file://./state");
+/// Fails if `name` is already taken, ignoring case unless `case_sensitive`.
+/// The profile called `except` doesn't count, so a rename can change casing.
+fn check_name_available(
+    profiles: &[Profile],
+    name: &str,
+    except: Option<&str>,
+    case_sensitive: bool,
+) -> Result<()> {
+    let existing = profiles.iter().filter(|p| Some(p.name.as_str()) != except).find(|p| {
+        if case_sensitive {
+            p.name == name
+        } else {
+            p.name.eq_ignore_ascii_case(name)
+        }
+    });
+
+    match existing {
+        Some(p) if p.name == name => Err(ConfigError::ProfileExists(name.to_string())),
+        Some(p) => Err(ConfigError::NameCollision {
+            name: name.to_string(),
+            existing: p.name.clone(),
+        }),
+        None => Ok(()),
     }
+}
 
-    #[test]
-    fn test_json_serialization() {
-        let profiles = vec![
-            Profile::new("dev".to_string(), "s3://pulumi-state-dev".to_string()),
-            Profile::new("prod".to_string(), "s3://pulumi-state-prod".to_string()),
-        ];
+/// Checks that `name` is valid and not yet taken in `profiles`, the way
+/// `add_profile` will, so a prompt can reject it before anything is saved.
+pub fn check_new_profile_name(profiles: &[Profile], name: &str, case_sensitive: bool) -> Result<()> {
+    validate_profile_name(name)?;
+    check_name_available(profiles, name, None, case_sensitive)
+}
 
-        let json = serde_json::to_string(&profiles).unwrap();
-        let deserialized: Vec<Profile> = serde_json::from_str(&json).unwrap();
+fn backup_path(profiles_path: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => profiles_path.with_extension("json.bak"),
+        n => profiles_path.with_extension(format!("json.bak.{n}")),
+    }
+}
 
-        assert_eq!(profiles.len(), deserialized.len());
-        assert_eq!(profiles[0].name, deserialized[0].name);
-        assert_eq!(profiles[0].backend, deserialized[0].backend);
+/// Copies the profiles file to `profiles.json.bak`, first shifting older
+/// backups to `profiles.json.bak.1`, `.bak.2`, ... so that at most `keep` remain.
+pub fn backup_profiles(profiles_path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 || !profiles_path.exists() {
+        return Ok(());
+    }
+
+    for index in (1..keep).rev() {
+        let older = backup_path(profiles_path, index - 1);
+        if older.exists() {
+            log::debug!("Rotating backup {}", older.display());
+            fs::rename(&older, backup_path(profiles_path, index))
+                .io_context(|| format!("Failed to rotate backup {older:?}"))?;
+        }
     }
-}
\ No newline at end of file
+
+    let backup = backup_path(profiles_path, 0);
+    log::debug!("Backing up {} to {}", profiles_path.display(), backup.display());
+    fs::copy(profiles_path, &backup)
+        .io_context(|| format!("Failed to back up profiles file to {backup:?}"))?;
+
+    Ok(())
+}
+
+/// Takes the exclusive lock guarding `profiles_path`; it is held until the
+/// returned file is dropped.
+fn lock_profiles(profiles_path: &Path) -> Result<fs::File> {
+    // Lock a sibling file rather than profiles.json itself so the lock
+    // survives the file being replaced on save
+    if let Some(parent) = profiles_path.parent() {
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    let lock_path = profiles_path.with_extension("json.lock");
+    log::debug!("Locking {}", lock_path.display());
+    let lock_file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .io_context(|| format!("Failed to open lock file: {lock_path:?}"))?;
+    lock_file
+        .lock()
+        .io_context(|| format!("Failed to lock profiles file: {lock_path:?}"))?;
+
+    Ok(lock_file)
+}
+
+pub fn add_profile(profiles_path: &Path, mut profile: Profile, force: bool, options: &WriteOptions) -> Result<()> {
+    validate_profile_name(&profile.name)?;
+    let groups = load_profile_groups(profiles_path)?;
+    if let Some(group) = &profile.group
+        && !groups.iter().any(|g| &g.name == group)
+    {
+        return Err(ConfigError::UnknownGroup(group.clone()));
+    }
+    for (name, value) in &profile.env {
+        check_extra_env_var(name, value)?;
+    }
+    if !force {
+        validate_backend_url(&profile.full_backend(&groups))?;
+    }
+    profile.backend = normalize_backend(&profile.backend);
+
+    update_profiles(profiles_path, options, |profiles| {
+        check_name_available(profiles, &profile.name, None, options.case_sensitive)?;
+
+        // Catches the same backend spelled with or without a trailing slash
+        if !force
+            && let Some(existing) =
+                profiles.iter().find(|p| normalize_backend(&p.backend) == profile.backend)
+        {
+            return Err(ConfigError::BackendInUse {
+                name: existing.name.clone(),
+                backend: existing.backend.clone(),
+            });
+        }
+
+        profiles.push(profile);
+        Ok(())
+    })
+}
+
+pub fn edit_profile(
+    profiles_path: &Path,
+    name: &str,
+    new_backend: String,
+    force: bool,
+    options: &WriteOptions,
+) -> Result<()> {
+    // Without a scheme it may be a suffix, which only the profile's group can complete
+    let is_suffix = !new_backend.contains("://");
+    if !force && !is_suffix {
+        validate_backend_url(&new_backend)?;
+    }
+    let groups = if is_suffix { load_profile_groups(profiles_path)? } else { Vec::new() };
+
+    update_profiles(profiles_path, options, |profiles| {
+        // Find and update the profile
+        let profile = profiles
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| ConfigError::ProfileNotFound(name.to_string()))?;
+        if !force && is_suffix {
+            let edited = Profile {
+                backend: new_backend.clone(),
+                ..profile.clone()
+            };
+            validate_backend_url(&edited.full_backend(&groups))?;
+        }
+        profile.backend = normalize_backend(&new_backend);
+        Ok(())
+    })
+}
+
+pub fn rename_profile(profiles_path: &Path, old_name: &str, new_name: &str, options: &WriteOptions) -> Result<()> {
+    validate_profile_name(new_name)?;
+
+    update_profiles(profiles_path, options, |profiles| {
+        // A missing profile is reported before a taken name, whichever the user got wrong
+        let index = profiles
+            .iter()
+            .position(|p| p.name == old_name)
+            .ok_or_else(|| ConfigError::ProfileNotFound(old_name.to_string()))?;
+        check_name_available(profiles, new_name, Some(old_name), options.case_sensitive)?;
+
+        profiles[index].name = new_name.to_string();
+        Ok(())
+    })
+}
+
+/// Copies every field of `source` into a new profile called `new_name`. The
+/// copy starts with no usage history.
+pub fn clone_profile(profiles_path: &Path, source: &str, new_name: &str, options: &WriteOptions) -> Result<()> {
+    validate_profile_name(new_name)?;
+
+    update_profiles(profiles_path, options, |profiles| {
+        check_name_available(profiles, new_name, None, options.case_sensitive)?;
+
+        let mut clone = profiles
+            .iter()
+            .find(|p| p.name == source)
+            .cloned()
+            .ok_or_else(|| ConfigError::ProfileNotFound(source.to_string()))?;
+        clone.name = new_name.to_string();
+        clone.last_used = None;
+        clone.pinned = false;
+        clone.default = false;
+
+        profiles.push(clone);
+        Ok(())
+    })
+}
+
+/// Pins `name`, unpinning whichever profile was pinned before.
+pub fn pin_profile(profiles_path: &Path, name: &str, options: &WriteOptions) -> Result<()> {
+    update_profiles(profiles_path, options, |profiles| {
+        if !profiles.iter().any(|p| p.name == name) {
+            return Err(ConfigError::ProfileNotFound(name.to_string()));
+        }
+
+        for profile in profiles.iter_mut() {
+            profile.pinned = profile.name == name;
+        }
+        Ok(())
+    })
+}
+
+pub fn unpin_profile(profiles_path: &Path, name: &str, options: &WriteOptions) -> Result<()> {
+    update_profiles(profiles_path, options, |profiles| {
+        let profile = profiles
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| ConfigError::ProfileNotFound(name.to_string()))?;
+        profile.pinned = false;
+        Ok(())
+    })
+}
+
+/// Makes `name` the default profile, replacing any previous default.
+pub fn set_default_profile(profiles_path: &Path, name: &str, options: &WriteOptions) -> Result<()> {
+    update_profiles(profiles_path, options, |profiles| {
+        if !profiles.iter().any(|p| p.name == name) {
+            return Err(ConfigError::ProfileNotFound(name.to_string()));
+        }
+
+        for profile in profiles.iter_mut() {
+            profile.default = profile.name == name;
+        }
+        Ok(())
+    })
+}
+
+/// Records whether the access token of `name` lives in the OS keyring. Moving
+/// it there drops the plaintext `access_token` from the file.
+pub fn set_token_in_keyring(
+    profiles_path: &Path,
+    name: &str,
+    in_keyring: bool,
+    options: &WriteOptions,
+) -> Result<()> {
+    update_profiles(profiles_path, options, |profiles| {
+        let profile = profiles
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| ConfigError::ProfileNotFound(name.to_string()))?;
+        profile.access_token_in_keyring = in_keyring;
+        if in_keyring {
+            profile.access_token = None;
+        }
+        Ok(())
+    })
+}
+
+pub fn default_profile(profiles: &[Profile]) -> Option<&Profile> {
+    profiles.iter().find(|p| p.default)
+}
+
+pub fn delete_profile(profiles_path: &Path, name: &str, options: &WriteOptions) -> Result<()> {
+    delete_profiles(profiles_path, &[name], options).map(|_| ())
+}
+
+/// Removes every named profile in a single read-modify-write, failing without
+/// changes if any of them doesn't exist. Returns the number deleted.
+pub fn delete_profiles(profiles_path: &Path, names: &[&str], options: &WriteOptions) -> Result<usize> {
+    update_profiles(profiles_path, options, |profiles| {
+        if let Some(missing) = names.iter().find(|name| !profiles.iter().any(|p| &p.name == *name)) {
+            return Err(ConfigError::ProfileNotFound(missing.to_string()));
+        }
+
+        let original_len = profiles.len();
+        profiles.retain(|p| !names.contains(&p.name.as_str()));
+
+        Ok(original_len - profiles.len())
+    })
+}
+
+/// Keeps the first profile of each name and drops the rest. Returns the number removed.
+pub fn dedupe_profiles(profiles_path: &Path, options: &WriteOptions) -> Result<usize> {
+    update_profiles(profiles_path, options, |profiles| {
+        let original_len = profiles.len();
+        let mut seen: Vec<String> = Vec::new();
+        profiles.retain(|p| {
+            if seen.contains(&p.name) {
+                return false;
+            }
+            seen.push(p.name.clone());
+            true
+        });
+
+        Ok(original_len - profiles.len())
+    })
+}
+
+/// A backend rewritten by `replace_in_backends`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendReplacement {
+    pub name: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// The backends `replace_in_backends` would rewrite, in file order. Stored
+/// backends are searched, so for a group member that is only its suffix.
+pub fn plan_backend_replacements(profiles: &[Profile], from: &str, to: &str) -> Vec<BackendReplacement> {
+    if from.is_empty() {
+        return Vec::new();
+    }
+
+    profiles
+        .iter()
+        .filter(|p| p.backend.contains(from))
+        .map(|p| BackendReplacement {
+            name: p.name.clone(),
+            before: p.backend.clone(),
+            after: p.backend.replace(from, to),
+        })
+        .filter(|r| r.before != r.after)
+        .collect()
+}
+
+/// Replaces every occurrence of `from` with `to` in all backends, e.g. after
+/// moving state to a new bucket, in a single save. Returns the number of
+/// profiles changed. Unless `force`, nothing is saved if any rewritten backend
+/// is invalid.
+pub fn replace_in_backends(
+    profiles_path: &Path,
+    from: &str,
+    to: &str,
+    force: bool,
+    options: &WriteOptions,
+) -> Result<usize> {
+    if from.is_empty() {
+        return Err(ConfigError::InvalidBackend("Text to replace in backends cannot be empty".to_string()));
+    }
+    let groups = load_profile_groups(profiles_path)?;
+
+    update_profiles(profiles_path, options, |profiles| {
+        let mut changed = 0;
+        for profile in profiles.iter_mut() {
+            let after = profile.backend.replace(from, to);
+            if after == profile.backend {
+                continue;
+            }
+            profile.backend = after;
+            if !force {
+                validate_backend_url(&profile.full_backend(&groups))?;
+            }
+            changed += 1;
+        }
+
+        Ok(changed)
+    })
+}
+
+/// Records that `name` was just activated.
+pub fn mark_profile_used(profiles_path: &Path, name: &str) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+
+    // A profile from the overlay records its usage there
+    let local_path = get_local_profiles_path(profiles_path);
+    let target = if local_path.exists() && read_profiles_file(&local_path)?.iter().any(|p| p.name == name) {
+        local_path
+    } else {
+        profiles_path.to_path_buf()
+    };
+
+    // Recording usage isn't a user edit, so it mustn't rotate real backups away
+    let options = WriteOptions {
+        backups: 0,
+        ..WriteOptions::default()
+    };
+    update_profiles(&target, &options, |profiles| {
+        let profile = profiles
+            .iter_mut()
+            .find(|p| p.name == name)
+            .ok_or_else(|| ConfigError::ProfileNotFound(name.to_string()))?;
+        profile.last_used = Some(now);
+        Ok(())
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct PulumiCredentials {
+    #[serde(default)]
+    accounts: std::collections::BTreeMap<String, Value>,
+}
+
+/// Builds profiles for every backend logged into via `pulumi login` (the
+/// `accounts` map of credentials.json) that no existing profile points at.
+/// Names are derived from the backend host and made unique.
+pub fn import_from_credentials(credentials_path: &Path, existing: &[Profile]) -> Result<Vec<Profile>> {
+    log::debug!("Reading credentials from {}", credentials_path.display());
+    let content = fs::read_to_string(credentials_path)
+        .io_context(|| format!("Failed to read Pulumi credentials file: {credentials_path:?}"))?;
+    let credentials: PulumiCredentials =
+        serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+            context: "Failed to parse Pulumi credentials JSON".to_string(),
+            source,
+        })?;
+
+    let mut taken: Vec<String> = existing.iter().map(|p| p.name.clone()).collect();
+    let mut imported = Vec::new();
+
+    for backend in credentials.accounts.into_keys() {
+        if existing.iter().any(|p| p.backend == backend) {
+            continue;
+        }
+
+        let name = unique_name(&default_profile_name(&backend), &taken);
+        taken.push(name.clone());
+        imported.push(Profile::new(name, backend));
+    }
+
+    Ok(imported)
+}
+
+/// Appends `-2`, `-3`, ... to `base` until it no longer collides with `taken`.
+fn unique_name(base: &str, taken: &[String]) -> String {
+    let mut name = base.to_string();
+    let mut suffix = 2;
+    while taken.contains(&name) {
+        name = format!("{base}-{suffix}");
+        suffix += 1;
+    }
+    name
+}
+
+/// Derives a profile name from a backend URL's host, e.g. `api.pulumi.com`
+/// or the bucket of `s3://bucket/path`. Hostless URLs such as `file://~` use
+/// their last path segment, falling back to `local`.
+fn default_profile_name(backend: &str) -> String {
+    let location = backend.split_once("://").map_or(backend, |(_, rest)| rest);
+    let location = location.split(['?', '#']).next().unwrap_or_default();
+    let host = location.split('/').next().unwrap_or_default();
+
+    let name = if host.is_empty() || host == "~" || host == "." {
+        location.trim_end_matches('/').rsplit('/').next().unwrap_or_default()
+    } else {
+        host
+    };
+
+    // Hosts may carry ports or userinfo, which aren't valid in profile names
+    let name: String = name
+        .chars()
+        .map(|c| if is_profile_name_char(c) { c } else { '-' })
+        .take(MAX_PROFILE_NAME_LEN)
+        .collect();
+
+    match name.as_str() {
+        "" | "~" | "." | "-" => "local".to_string(),
+        _ => name,
+    }
+}
+
+/// How `import_profiles` resolves a name that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the existing profile and drop the imported one
+    Skip,
+    /// Replace the existing profile with the imported one
+    Overwrite,
+    /// Import under a fresh name such as `dev-2`
+    Rename,
+}
+
+/// What `import_profiles` did with each imported profile.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub added: Vec<String>,
+    pub skipped: Vec<String>,
+    pub overwritten: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Writes every profile, with the local overlay merged in as `--list` shows
+/// them, to `export_path` as pretty JSON.
+pub fn export_profiles(source: &ProfilesSource, export_path: &Path) -> Result<usize> {
+    let profiles = source.load()?;
+    write_profiles_file(export_path, &profiles, source.load_groups()?)?;
+    Ok(profiles.len())
+}
+
+/// Merges the profiles in `import_path` into the profiles file, resolving
+/// name conflicts with `strategy`.
+pub fn import_profiles(
+    profiles_path: &Path,
+    import_path: &Path,
+    strategy: MergeStrategy,
+    options: &WriteOptions,
+) -> Result<ImportSummary> {
+    let incoming = read_profiles_file(import_path)?;
+
+    update_profiles(profiles_path, options, |profiles| {
+        let mut summary = ImportSummary::default();
+
+        // Importing never moves the pin or the default
+        for profile in incoming.into_iter().map(|p| Profile { pinned: false, default: false, ..p }) {
+            let Some(index) = profiles.iter().position(|p| p.name == profile.name) else {
+                summary.added.push(profile.name.clone());
+                profiles.push(profile);
+                continue;
+            };
+
+            match strategy {
+                MergeStrategy::Skip => summary.skipped.push(profile.name),
+                MergeStrategy::Overwrite => {
+                    summary.overwritten.push(profile.name.clone());
+                    profiles[index] = profile;
+                }
+                MergeStrategy::Rename => {
+                    let taken: Vec<String> = profiles.iter().map(|p| p.name.clone()).collect();
+                    let new_name = unique_name(&profile.name, &taken);
+                    summary.renamed.push((profile.name.clone(), new_name.clone()));
+                    profiles.push(Profile {
+                        name: new_name,
+                        ..profile
+                    });
+                }
+            }
+        }
+
+        Ok(summary)
+    })
+}
+
+/// What the current_profile file records: the active profile and the backend
+/// it was activated with, so the activation stays meaningful after the
+/// profile is edited or deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CurrentProfile {
+    pub name: String,
+    /// `None` for files written before the backend was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+}
+
+/// Returns the name stored in the current_profile file, if any.
+pub fn read_current_profile(current_profile_path: &Path) -> Result<Option<String>> {
+    Ok(read_current_profile_entry(current_profile_path)?.map(|current| current.name))
+}
+
+/// Reads the current_profile file, which holds `{"name": ..., "backend": ...}`.
+/// Older files hold just the name as plain text.
+pub fn read_current_profile_entry(current_profile_path: &Path) -> Result<Option<CurrentProfile>> {
+    if !current_profile_path.exists() {
+        log::debug!("No current profile file at {}", current_profile_path.display());
+        return Ok(None);
+    }
+    log::debug!("Reading current profile from {}", current_profile_path.display());
+
+    let content = fs::read_to_string(current_profile_path).io_context(|| {
+        format!("Failed to read current profile file: {current_profile_path:?}")
+    })?;
+    parse_current_profile(&content)
+}
+
+fn parse_current_profile(content: &str) -> Result<Option<CurrentProfile>> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Ok(None);
+    }
+    // Profile names can't contain braces, so this can't be a legacy file
+    if !content.starts_with('{') {
+        return Ok(Some(CurrentProfile {
+            name: content.to_string(),
+            backend: None,
+        }));
+    }
+
+    let current = serde_json::from_str(content).map_err(|source| ConfigError::Parse {
+        context: "Failed to parse the current_profile file; run --deactivate to reset it".to_string(),
+        source,
+    })?;
+    Ok(Some(current))
+}
+
+/// Refuses to delete the active profile unless `force` is set, so the
+/// current_profile file isn't left pointing at nothing. Returns whether the
+/// active profile is among `names`, in which case the caller should clear it.
+pub fn check_active_deletion(current_profile_path: &Path, names: &[&str], force: bool) -> Result<bool> {
+    let Some(active) = read_current_profile(current_profile_path)? else {
+        return Ok(false);
+    };
+
+    if !names.contains(&active.as_str()) {
+        return Ok(false);
+    }
+
+    if !force {
+        return Err(ConfigError::ProfileActive(active));
+    }
+
+    Ok(true)
+}
+
+pub fn clear_current_profile(current_profile_path: &Path) -> Result<()> {
+    if current_profile_path.exists() {
+        log::debug!("Removing {}", current_profile_path.display());
+        fs::remove_file(current_profile_path).io_context(|| {
+            format!("Failed to remove current profile file: {current_profile_path:?}")
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Records `name` as the active profile, along with the `backend` it was
+/// activated with when known.
+pub fn write_current_profile(current_profile_path: &Path, name: &str, backend: Option<&str>) -> Result<()> {
+    // Create .pulumi directory if it doesn't exist
+    if let Some(parent) = current_profile_path.parent() {
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+
+    log::debug!("Writing current profile '{}' to {}", name, current_profile_path.display());
+    let current = CurrentProfile {
+        name: name.to_string(),
+        backend: backend.map(str::to_string),
+    };
+    let content = serde_json::to_string(&current).expect("current profile serializes to JSON");
+    write_atomic(current_profile_path, content.as_bytes()).io_context(|| {
+        format!("Failed to write current profile file: {current_profile_path:?}")
+    })?;
+
+    Ok(())
+}
+
+/// Holds the name of the profile last picked in the selector, which opens on
+/// it next time.
+pub fn get_selector_cursor_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("selector_cursor"))
+}
+
+/// The profile name stored by `write_selector_cursor`. Missing or unreadable
+/// state just means the selector opens on the active profile.
+pub fn read_selector_cursor(cursor_path: &Path) -> Option<String> {
+    let name = fs::read_to_string(cursor_path).ok()?;
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Stores `name` for the selector's next run.
+pub fn write_selector_cursor(cursor_path: &Path, name: &str) -> Result<()> {
+    if let Some(parent) = cursor_path.parent() {
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    write_atomic(cursor_path, name.as_bytes())
+        .io_context(|| format!("Failed to write selector state: {cursor_path:?}"))
+}
+
+pub fn get_pulumi_profiles_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("profiles.json"))
+}
+
+pub fn get_credentials_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("credentials.json"))
+}
+
+pub fn get_current_profile_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("current_profile"))
+}
+
+/// Resolves the directory holding profiles.json and current_profile.
+///
+/// `PULUMI_HOME` wins (matching the Pulumi CLI), then `$XDG_CONFIG_HOME/pulumi`,
+/// then `~/.pulumi`.
+pub(crate) fn get_pulumi_home() -> Result<PathBuf> {
+    resolve_pulumi_home(
+        env::var_os("PULUMI_HOME"),
+        env::var_os("XDG_CONFIG_HOME"),
+        dirs::home_dir(),
+    )
+}
+
+fn resolve_pulumi_home(
+    pulumi_home: Option<OsString>,
+    xdg_config_home: Option<OsString>,
+    home_dir: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(dir) = pulumi_home.filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+
+    if let Some(dir) = xdg_config_home.filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(dir).join("pulumi"));
+    }
+
+    let home_dir = home_dir.ok_or(ConfigError::HomeDirUnavailable)?;
+
+    Ok(home_dir.join(".pulumi"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Tests that touch process-wide environment variables must not interleave.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// The CLI's defaults, for the changes the tests make.
+    const OPTIONS: &WriteOptions = &WriteOptions {
+        backups: DEFAULT_BACKUPS,
+        dry_run: false,
+        case_sensitive: false,
+    };
+
+    fn set_env(key: &str, value: Option<&str>) {
+        // SAFETY: guarded by ENV_LOCK so no other test reads the environment concurrently.
+        unsafe {
+            match value {
+                Some(v) => env::set_var(key, v),
+                None => env::remove_var(key),
+            }
+        }
+    }
+
+    #[test]
+    fn test_profile_creation() {
+        let profile = Profile::new("test".to_string(), "file://./state".to_string());
+        assert_eq!(profile.name, "test");
+        assert_eq!(profile.backend, "file://./state");
+    }
+
+    #[test]
+    fn test_json_serialization() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://pulumi-state-dev".to_string()),
+            Profile::new("prod".to_string(), "s3://pulumi-state-prod".to_string()),
+        ];
+
+        let json = serde_json::to_string(&profiles).unwrap();
+        let deserialized: Vec<Profile> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(profiles.len(), deserialized.len());
+        assert_eq!(profiles[0].name, deserialized[0].name);
+        assert_eq!(profiles[0].backend, deserialized[0].backend);
+    }
+
+    #[test]
+    fn test_pulumi_home_takes_precedence() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        set_env("PULUMI_HOME", Some("/tmp/pulumi-home"));
+        set_env("XDG_CONFIG_HOME", Some("/tmp/xdg"));
+
+        assert_eq!(
+            get_pulumi_profiles_path().unwrap(),
+            PathBuf::from("/tmp/pulumi-home/profiles.json")
+        );
+        assert_eq!(
+            get_current_profile_path().unwrap(),
+            PathBuf::from("/tmp/pulumi-home/current_profile")
+        );
+
+        set_env("PULUMI_HOME", None);
+        set_env("XDG_CONFIG_HOME", None);
+    }
+
+    #[test]
+    fn test_xdg_config_home_fallback() {
+        let _guard = ENV_LOCK.lock().unwrap();
+        set_env("PULUMI_HOME", None);
+        set_env("XDG_CONFIG_HOME", Some("/tmp/xdg"));
+
+        assert_eq!(
+            get_pulumi_profiles_path().unwrap(),
+            PathBuf::from("/tmp/xdg/pulumi/profiles.json")
+        );
+        assert_eq!(
+            get_current_profile_path().unwrap(),
+            PathBuf::from("/tmp/xdg/pulumi/current_profile")
+        );
+
+        set_env("XDG_CONFIG_HOME", None);
+    }
+
+    #[test]
+    fn test_home_dir_default() {
+        let resolved = resolve_pulumi_home(None, Some(OsString::new()), Some("/home/me".into()));
+        assert_eq!(resolved.unwrap(), PathBuf::from("/home/me/.pulumi"));
+
+        assert!(matches!(resolve_pulumi_home(None, None, None), Err(ConfigError::HomeDirUnavailable)));
+    }
+
+    #[test]
+    fn test_read_creates_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("profiles.json");
+
+        let profiles = read_pulumi_profiles(&path).unwrap();
+
+        assert!(profiles.is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_add_edit_delete_with_explicit_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        assert!(matches!(
+            add_profile(&path, Profile::new("dev".to_string(), "s3://other".to_string()), false, OPTIONS),
+            Err(ConfigError::ProfileExists(name)) if name == "dev"
+        ));
+
+        edit_profile(&path, "dev", "s3://dev-2".to_string(), false, OPTIONS).unwrap();
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].backend, "s3://dev-2");
+
+        delete_profile(&path, "dev", OPTIONS).unwrap();
+        assert!(read_pulumi_profiles(&path).unwrap().is_empty());
+        assert!(matches!(delete_profile(&path, "dev", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+    }
+
+    #[test]
+    fn test_full_backend_with_and_without_group() {
+        let groups = [ProfileGroup {
+            name: "company".to_string(),
+            base: "s3://company-state/".to_string(),
+        }];
+        let member = |backend: &str, group: Option<&str>| Profile {
+            group: group.map(str::to_string),
+            ..Profile::new("p".to_string(), backend.to_string())
+        };
+
+        assert_eq!(member("s3://solo", None).full_backend(&groups), "s3://solo");
+        assert_eq!(member("dev", Some("company")).full_backend(&groups), "s3://company-state/dev");
+        assert_eq!(member("/team/prod", Some("company")).full_backend(&groups), "s3://company-state/team/prod");
+        assert_eq!(member("", Some("company")).full_backend(&groups), "s3://company-state/");
+        assert_eq!(member("s3://elsewhere", Some("company")).full_backend(&groups), "s3://elsewhere");
+        assert_eq!(member("dev", Some("missing")).full_backend(&groups), "dev");
+
+        let no_slash = [ProfileGroup {
+            name: "company".to_string(),
+            base: "s3://company-state".to_string(),
+        }];
+        assert_eq!(member("dev", Some("company")).full_backend(&no_slash), "s3://company-state/dev");
+    }
+
+    #[test]
+    fn test_groups_survive_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(
+            &path,
+            r#"{"version": 1, "groups": [{"name": "company", "base": "s3://company-state"}], "profiles": []}"#,
+        )
+        .unwrap();
+
+        let member = Profile {
+            group: Some("company".to_string()),
+            ..Profile::new("dev".to_string(), "dev".to_string())
+        };
+        add_profile(&path, member, false, OPTIONS).unwrap();
+        edit_profile(&path, "dev", "dev-2".to_string(), false, OPTIONS).unwrap();
+
+        let groups = load_profile_groups(&path).unwrap();
+        assert_eq!(groups.len(), 1);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles[0].backend, "dev-2", "only the suffix is stored");
+        assert_eq!(
+            resolve_group_backends(profiles, &groups)[0].backend,
+            "s3://company-state/dev-2"
+        );
+
+        let stray = Profile {
+            group: Some("nope".to_string()),
+            ..Profile::new("stray".to_string(), "x".to_string())
+        };
+        assert!(matches!(add_profile(&path, stray, false, OPTIONS), Err(ConfigError::UnknownGroup(g)) if g == "nope"));
+        assert!(add_profile(&path, Profile::new("bare".to_string(), "dev".to_string()), false, OPTIONS).is_err());
+    }
+
+    #[test]
+    fn test_rename_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, OPTIONS).unwrap();
+
+        assert!(matches!(rename_profile(&path, "dev", "prod", OPTIONS), Err(ConfigError::ProfileExists(_))));
+        assert!(matches!(rename_profile(&path, "missing", "other", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+        assert!(matches!(rename_profile(&path, "missing", "prod", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+
+        rename_profile(&path, "dev", "development", OPTIONS).unwrap();
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles[0].name, "development");
+        assert_eq!(profiles[0].backend, "s3://dev");
+    }
+
+    #[test]
+    fn test_current_profile_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("current_profile");
+
+        assert_eq!(read_current_profile(&path).unwrap(), None);
+
+        write_current_profile(&path, "dev", Some("s3://dev")).unwrap();
+        assert_eq!(read_current_profile(&path).unwrap().as_deref(), Some("dev"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"name":"dev","backend":"s3://dev"}"#);
+        assert_eq!(
+            read_current_profile_entry(&path).unwrap(),
+            Some(CurrentProfile {
+                name: "dev".to_string(),
+                backend: Some("s3://dev".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_current_profile_formats() {
+        let bare = |name: &str| {
+            Some(CurrentProfile {
+                name: name.to_string(),
+                backend: None,
+            })
+        };
+
+        // Files written before the backend was recorded hold a bare name
+        assert_eq!(parse_current_profile("dev\n").unwrap(), bare("dev"));
+        assert_eq!(parse_current_profile("  \n").unwrap(), None);
+        assert_eq!(parse_current_profile(r#"{"name": "dev"}"#).unwrap(), bare("dev"));
+        assert_eq!(
+            parse_current_profile(r#"{"name": "prod", "backend": "s3://prod"}"#).unwrap().unwrap().backend.as_deref(),
+            Some("s3://prod")
+        );
+        assert!(matches!(parse_current_profile("{broken"), Err(ConfigError::Parse { .. })));
+    }
+
+    #[test]
+    fn test_validate_backend_url() {
+        for url in [
+            "s3://bucket/path",
+            "gs://bucket",
+            "azblob://container",
+            "file://./state",
+            "file:///var/state",
+            "https://api.pulumi.com",
+            "http://localhost:8080",
+        ] {
+            assert!(validate_backend_url(url).is_ok(), "{url} should be valid");
+        }
+
+        for url in ["s3:/bucket", "ftp://host", "s3://", "bucket", "s3://my bucket"] {
+            assert!(
+                matches!(validate_backend_url(url), Err(ConfigError::InvalidBackend(_))),
+                "{url} should be invalid"
+            );
+        }
+
+        let err = validate_backend_url("ftp://host").unwrap_err().to_string();
+        assert!(err.contains("s3://") && err.contains("azblob://"));
+    }
+
+    #[test]
+    fn test_normalize_backend() {
+        assert_eq!(normalize_backend("s3://bucket/path/"), "s3://bucket/path");
+        assert_eq!(normalize_backend("s3://bucket/path"), "s3://bucket/path");
+        assert_eq!(normalize_backend("file://./state/"), "file://./state");
+        assert_eq!(normalize_backend("https://api.pulumi.com/"), "https://api.pulumi.com");
+        // Only one slash is dropped, and never the last of a location
+        assert_eq!(normalize_backend("gs://bucket//"), "gs://bucket/");
+        assert_eq!(normalize_backend("file:///"), "file:///");
+        assert_eq!(normalize_backend("s3://"), "s3://");
+        assert_eq!(normalize_backend("s3://bucket/?region=eu-west-1"), "s3://bucket/?region=eu-west-1");
+    }
+
+    #[test]
+    fn test_add_rejects_trailing_slash_variant_of_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://state/dev/".to_string()), false, OPTIONS).unwrap();
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[0].backend, "s3://state/dev");
+
+        let err = add_profile(&path, Profile::new("dev2".to_string(), "s3://state/dev".to_string()), false, OPTIONS)
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::BackendInUse { name, .. } if name == "dev"));
+        add_profile(&path, Profile::new("dev2".to_string(), "s3://state/dev/".to_string()), true, OPTIONS).unwrap();
+
+        edit_profile(&path, "dev2", "file://./state/".to_string(), false, OPTIONS).unwrap();
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[1].backend, "file://./state");
+    }
+
+    #[test]
+    fn test_validate_profile_name() {
+        for name in ["dev", "prod-eu.1", "team_a", "A", &"x".repeat(MAX_PROFILE_NAME_LEN)] {
+            assert!(validate_profile_name(name).is_ok(), "{name} should be valid");
+        }
+
+        for name in [
+            "",
+            "my profile",
+            "team/dev",
+            "dev\n",
+            "tab\there",
+            "café",
+            "ｄｅｖ",
+            "dev\u{200b}",
+            &"x".repeat(MAX_PROFILE_NAME_LEN + 1),
+        ] {
+            assert!(
+                matches!(validate_profile_name(name), Err(ConfigError::InvalidName(_))),
+                "{name:?} should be invalid"
+            );
+        }
+
+        let err = validate_profile_name("my profile").unwrap_err().to_string();
+        assert!(err.contains("letters, digits"));
+    }
+
+    #[test]
+    fn test_add_and_rename_reject_invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        assert!(add_profile(&path, Profile::new("my dev".to_string(), "s3://dev".to_string()), true, OPTIONS).is_err());
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        assert!(rename_profile(&path, "dev", "dev/old", OPTIONS).is_err());
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[0].name, "dev");
+    }
+
+    #[test]
+    fn test_add_profile_validation_and_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        assert!(matches!(
+            add_profile(&path, Profile::new("typo".to_string(), "s3:/bucket".to_string()), false, OPTIONS),
+            Err(ConfigError::InvalidBackend(_))
+        ));
+        add_profile(&path, Profile::new("custom".to_string(), "custom-backend".to_string()), true, OPTIONS).unwrap();
+        assert!(edit_profile(&path, "custom", "nope".to_string(), false, OPTIONS).is_err());
+        edit_profile(&path, "custom", "still-custom".to_string(), true, OPTIONS).unwrap();
+    }
+
+    #[test]
+    fn test_description_round_trip() {
+        let mut described = Profile::new("dev".to_string(), "s3://dev".to_string());
+        described.description = Some("Team sandbox".to_string());
+        let plain = Profile::new("prod".to_string(), "s3://prod".to_string());
+
+        let json = serde_json::to_string(&vec![described, plain]).unwrap();
+        assert!(!json.contains("\"description\":null"));
+
+        let deserialized: Vec<Profile> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized[0].description.as_deref(), Some("Team sandbox"));
+        assert_eq!(deserialized[1].description, None);
+
+        // Files written before descriptions existed must still parse
+        let legacy: Vec<Profile> =
+            serde_json::from_str(r#"[{"name": "old", "backend": "file://./state"}]"#).unwrap();
+        assert_eq!(legacy[0].description, None);
+    }
+
+    #[test]
+    fn test_notes_survive_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(
+            &path,
+            r#"[{"name": "prod", "backend": "s3://prod", "notes": "Owned by infra; ask in #platform first"},
+                {"name": "old", "backend": "file://./state"}]"#,
+        )
+        .unwrap();
+
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        rename_profile(&path, "prod", "production", OPTIONS).unwrap();
+
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles[0].notes.as_deref(), Some("Owned by infra; ask in #platform first"));
+        assert_eq!(profiles[1].notes, None, "files without notes still parse");
+        assert!(!fs::read_to_string(&path).unwrap().contains("\"notes\": null"));
+    }
+
+    #[test]
+    fn test_filter_by_tags() {
+        let tagged = |name: &str, tags: &[&str]| {
+            let mut profile = Profile::new(name.to_string(), format!("s3://{name}"));
+            profile.tags = tags.iter().map(|t| t.to_string()).collect();
+            profile
+        };
+        let profiles = vec![
+            tagged("dev", &["dev", "team-infra"]),
+            tagged("prod", &["prod", "team-infra"]),
+            tagged("scratch", &[]),
+        ];
+        let names = |tags: &[&str]| {
+            let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+            filter_by_tags(profiles.clone(), &tags)
+                .into_iter()
+                .map(|p| p.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(&[]), vec!["dev", "prod", "scratch"]);
+        assert_eq!(names(&["team-infra"]), vec!["dev", "prod"]);
+        assert_eq!(names(&["team-infra", "prod"]), vec!["prod"]);
+        assert!(names(&["dev", "prod"]).is_empty());
+    }
+
+    #[test]
+    fn test_matches_glob() {
+        // `*`
+        assert!(matches_glob("acme-*", "acme-dev"));
+        assert!(matches_glob("acme-*", "acme-"));
+        assert!(matches_glob("*-prod", "acme-prod"));
+        assert!(matches_glob("a*e*d", "acme-staged"));
+        assert!(matches_glob("*", "anything"));
+        assert!(!matches_glob("acme-*", "other-acme-dev"));
+        assert!(!matches_glob("*-prod", "acme-prod-eu"));
+
+        // `?`
+        assert!(matches_glob("env-?", "env-1"));
+        assert!(!matches_glob("env-?", "env-"));
+        assert!(!matches_glob("env-?", "env-12"));
+        assert!(matches_glob("??-*", "eu-west"));
+
+        // Literal patterns match the whole name, ignoring case
+        assert!(matches_glob("acme-dev", "ACME-dev"));
+        assert!(!matches_glob("acme", "acme-dev"));
+        assert!(!matches_glob("acme-dev", "acme"));
+        assert!(matches_glob("", ""));
+        assert!(!matches_glob("", "dev"));
+    }
+
+    #[test]
+    fn test_filter_by_glob() {
+        let profiles = vec![
+            Profile::new("acme-dev".to_string(), "s3://acme-dev".to_string()),
+            Profile::new("acme-prod".to_string(), "s3://acme-prod".to_string()),
+            Profile::new("other".to_string(), "s3://acme-other".to_string()),
+        ];
+        let names: Vec<String> = filter_by_glob(profiles, "acme-*").into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["acme-dev", "acme-prod"], "only names are matched, not backends");
+    }
+
+    #[test]
+    fn test_filter_by_query() {
+        let profiles = vec![
+            Profile::new("prod-eu".to_string(), "s3://state-eu".to_string()),
+            Profile::new("dev".to_string(), "file://~".to_string()),
+            Profile::new("Production-US".to_string(), "s3://state-us".to_string()),
+        ];
+        let names = |query: &str| {
+            filter_by_query(profiles.clone(), query)
+                .into_iter()
+                .map(|p| p.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("PROD"), vec!["prod-eu", "Production-US"]);
+        assert_eq!(names("state-us"), vec!["Production-US"], "backends match too");
+        assert_eq!(names(""), vec!["prod-eu", "dev", "Production-US"]);
+        assert!(names("pd").is_empty(), "substrings, not fuzzy matches");
+    }
+
+    #[test]
+    fn test_index_follows_list_order() {
+        let profiles = vec![
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+        ];
+
+        let numbered = |sort: SortKey| -> Vec<(usize, &str)> {
+            ordered_profiles(&profiles, sort)
+                .into_iter()
+                .map(|(i, p)| (i, p.name.as_str()))
+                .collect()
+        };
+        assert_eq!(numbered(SortKey::None), vec![(1, "prod"), (2, "dev")]);
+        assert_eq!(numbered(SortKey::Name), vec![(1, "dev"), (2, "prod")]);
+
+        assert_eq!(profile_at_index(&profiles, SortKey::None, 2).unwrap().name, "dev");
+        assert_eq!(profile_at_index(&profiles, SortKey::Name, 2).unwrap().name, "prod");
+        assert!(profile_at_index(&profiles, SortKey::Name, 0).is_none());
+        assert!(profile_at_index(&profiles, SortKey::Name, 3).is_none());
+    }
+
+    #[test]
+    fn test_find_by_backend_ignores_case() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://Team-State/dev".to_string()),
+            Profile::new("prod".to_string(), "s3://team-state/prod".to_string()),
+            Profile::new("local".to_string(), "file://~".to_string()),
+        ];
+        let names = |needle: &str| {
+            find_by_backend(&profiles, needle)
+                .into_iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("TEAM-state"), vec!["dev", "prod"]);
+        assert_eq!(names("/prod"), vec!["prod"]);
+        assert!(names("gs://").is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_adds_keep_every_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let profile = Profile::new(format!("p{i}"), format!("s3://bucket-{i}"));
+                    add_profile(&path, profile, false, OPTIONS).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 16);
+        for i in 0..16 {
+            assert!(profiles.iter().any(|p| p.name == format!("p{i}")));
+        }
+    }
+
+    #[test]
+    fn test_save_replaces_file_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let tmp_path = dir.path().join("profiles.json.tmp");
+
+        // A leftover temp file from an interrupted write must not leak into the result
+        fs::write(&path, "[]").unwrap();
+        fs::write(&tmp_path, "[{\"name\": \"trunc").unwrap();
+
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+        ];
+        save_pulumi_profiles(&path, &profiles).unwrap();
+
+        assert!(!tmp_path.exists());
+        let content = fs::read_to_string(&path).unwrap();
+        let parsed = parse_profiles(&content).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[1].name, "prod");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(&path, "[]").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        save_pulumi_profiles(&path, &[]).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_env_vars_only_export_token_for_cloud_backends() {
+        let mut cloud = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        cloud.access_token = Some("pul-123".to_string());
+        assert_eq!(
+            cloud.env_vars(),
+            vec![
+                ("PULUMI_BACKEND_URL", "https://api.pulumi.com".to_string()),
+                ("PULUMI_ACCESS_TOKEN", "pul-123".to_string()),
+            ]
+        );
+
+        let mut s3 = Profile::new("s3".to_string(), "s3://bucket".to_string());
+        s3.access_token = Some("stray-token".to_string());
+        assert_eq!(s3.env_vars(), vec![("PULUMI_BACKEND_URL", "s3://bucket".to_string())]);
+
+        let tokenless = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        assert_eq!(tokenless.env_vars().len(), 1);
+    }
+
+    #[test]
+    fn test_env_vars_export_passphrase_file_for_self_managed_backends() {
+        let mut s3 = Profile::new("s3".to_string(), "s3://bucket".to_string());
+        s3.passphrase_file = Some(PathBuf::from("/home/me/.pulumi/passphrase"));
+        assert_eq!(
+            s3.env_vars(),
+            vec![
+                ("PULUMI_BACKEND_URL", "s3://bucket".to_string()),
+                ("PULUMI_CONFIG_PASSPHRASE_FILE", "/home/me/.pulumi/passphrase".to_string()),
+            ]
+        );
+
+        let mut cloud = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        cloud.passphrase_file = Some(PathBuf::from("/home/me/.pulumi/passphrase"));
+        assert_eq!(cloud.env_vars().len(), 1);
+    }
+
+    #[test]
+    fn test_env_vars_are_all_managed() {
+        let mut profile = Profile::new("s3".to_string(), "s3://bucket".to_string());
+        profile.access_token = Some("token".to_string());
+        profile.passphrase_file = Some(PathBuf::from("/tmp/passphrase"));
+        let mut cloud = profile.clone();
+        cloud.backend = "https://api.pulumi.com".to_string();
+
+        for (name, _) in profile.env_vars().into_iter().chain(cloud.env_vars()) {
+            assert!(MANAGED_ENV_VARS.contains(&name), "{name} is not in MANAGED_ENV_VARS");
+        }
+    }
+
+    #[test]
+    fn test_extra_env_vars_follow_the_backend() {
+        let mut profile = Profile::new("eu".to_string(), "s3://bucket".to_string());
+        profile.env.insert("AWS_REGION".to_string(), "eu-west-1".to_string());
+        profile.env.insert("AWS_PROFILE".to_string(), "state".to_string());
+
+        assert_eq!(
+            profile.env_vars(),
+            vec![
+                ("PULUMI_BACKEND_URL", "s3://bucket".to_string()),
+                ("AWS_PROFILE", "state".to_string()),
+                ("AWS_REGION", "eu-west-1".to_string()),
+            ]
+        );
+
+        profile.env.insert("PATH".to_string(), "/tmp/evil".to_string());
+        profile.env.insert("GOOGLE_PROJECT".to_string(), "$(rm -rf ~)".to_string());
+        assert_eq!(profile.env_vars().len(), 3, "unsafe entries are skipped");
+    }
+
+    #[test]
+    fn test_check_extra_env_var_rejects_reserved_and_unsafe() {
+        assert!(check_extra_env_var("AWS_REGION", "us-east-1").is_ok());
+        assert!(check_extra_env_var("_private", "").is_ok());
+
+        for name in ["PATH", "path", "LD_PRELOAD", "DYLD_INSERT_LIBRARIES", "PULUMI_BACKEND_URL", "1ST", "A-B", "A B", ""] {
+            assert!(
+                matches!(check_extra_env_var(name, "x"), Err(ConfigError::InvalidEnvVar(_))),
+                "{name} should be rejected"
+            );
+        }
+        for value in ["a\"b", "$HOME", "`id`", "a\\b", "a\nb"] {
+            assert!(check_extra_env_var("AWS_REGION", value).is_err(), "{value:?} should be rejected");
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let mut profile = Profile::new("dev".to_string(), "s3://dev".to_string());
+        profile.env.insert("LD_PRELOAD".to_string(), "/tmp/x.so".to_string());
+        assert!(matches!(add_profile(&path, profile, false, OPTIONS), Err(ConfigError::InvalidEnvVar(_))));
+    }
+
+    #[test]
+    fn test_extra_env_var_names_are_deduplicated() {
+        let mut dev = Profile::new("dev".to_string(), "s3://dev".to_string());
+        dev.env.insert("AWS_REGION".to_string(), "eu-west-1".to_string());
+        let mut prod = Profile::new("prod".to_string(), "gs://prod".to_string());
+        prod.env.insert("CLOUDSDK_CORE_PROJECT".to_string(), "prod".to_string());
+        prod.env.insert("AWS_REGION".to_string(), "us-east-1".to_string());
+        prod.env.insert("PATH".to_string(), "/bin".to_string());
+
+        assert_eq!(extra_env_var_names(&[dev, prod]), vec!["AWS_REGION", "CLOUDSDK_CORE_PROJECT"]);
+    }
+
+    #[test]
+    fn test_sort_by_recent() {
+        let used = |name: &str, last_used: Option<i64>| {
+            let mut profile = Profile::new(name.to_string(), format!("s3://{name}"));
+            profile.last_used = last_used;
+            profile
+        };
+        let mut profiles = vec![
+            used("zeta", None),
+            used("old", Some(100)),
+            used("alpha", None),
+            used("new", Some(300)),
+            used("also-new", Some(300)),
+        ];
+
+        sort_by_recent(&mut profiles);
+
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["also-new", "new", "old", "alpha", "zeta"]);
+    }
+
+
+    #[test]
+    fn test_sort_profiles_by_each_key() {
+        let profile = |name: &str, backend: &str, last_used: Option<i64>| Profile {
+            last_used,
+            ..Profile::new(name.to_string(), backend.to_string())
+        };
+        let profiles = vec![
+            profile("prod", "s3://b-prod", Some(100)),
+            profile("Dev", "s3://c-dev", None),
+            profile("staging", "gs://staging", Some(300)),
+            profile("alpha", "s3://a-alpha", None),
+        ];
+        let sorted = |key: SortKey| {
+            let mut profiles = profiles.clone();
+            sort_profiles(&mut profiles, key);
+            profiles.into_iter().map(|p| p.name).collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(SortKey::Name), ["alpha", "Dev", "prod", "staging"]);
+        assert_eq!(sorted(SortKey::Backend), ["staging", "alpha", "prod", "Dev"]);
+        assert_eq!(sorted(SortKey::Recent), ["staging", "prod", "alpha", "Dev"]);
+        assert_eq!(sorted(SortKey::None), ["prod", "Dev", "staging", "alpha"]);
+
+        // Ties break the same way under every key
+        let tied = vec![profile("beta", "s3://x", None), profile("Alpha", "s3://x", None)];
+        for key in [SortKey::Name, SortKey::Backend, SortKey::Recent] {
+            let mut tied = tied.clone();
+            sort_profiles(&mut tied, key);
+            assert_eq!(tied[0].name, "Alpha", "{key:?}");
+        }
+        assert_eq!(SortKey::default(), SortKey::Name);
+
+        for name in SORT_KEYS {
+            assert!(SortKey::from_name(name).is_some(), "{name}");
+        }
+        assert_eq!(SortKey::from_name("size"), None);
+    }
+    #[test]
+    fn test_mark_profile_used_persists_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+
+        mark_profile_used(&path, "dev").unwrap();
+
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert!(profiles[0].last_used.unwrap() > 0);
+        assert!(matches!(mark_profile_used(&path, "missing"), Err(ConfigError::ProfileNotFound(_))));
+    }
+
+    #[test]
+    fn test_most_recent_profile() {
+        let mut profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+        ];
+        assert!(most_recent_profile(&profiles).is_none());
+
+        profiles[0].last_used = Some(200);
+        profiles[1].last_used = Some(100);
+        assert_eq!(most_recent_profile(&profiles).unwrap().name, "dev");
+    }
+
+    #[test]
+    fn test_next_profile_wraps_around() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+            Profile::new("staging".to_string(), "s3://staging".to_string()),
+        ];
+        assert_eq!(next_profile(&profiles, Some("dev")).name, "prod");
+        assert_eq!(next_profile(&profiles, Some("staging")).name, "dev");
+        assert_eq!(previous_profile(&profiles, Some("prod")).name, "dev");
+        assert_eq!(previous_profile(&profiles, Some("dev")).name, "staging");
+
+        let single = &profiles[..1];
+        assert_eq!(next_profile(single, Some("dev")).name, "dev");
+        assert_eq!(previous_profile(single, Some("dev")).name, "dev");
+    }
+
+    #[test]
+    fn test_next_profile_without_an_active_one() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+        ];
+        assert_eq!(next_profile(&profiles, None).name, "dev");
+        assert_eq!(previous_profile(&profiles, None).name, "dev");
+        assert_eq!(next_profile(&profiles, Some("deleted")).name, "dev");
+    }
+
+    #[test]
+    fn test_default_profile_name() {
+        assert_eq!(default_profile_name("https://api.pulumi.com"), "api.pulumi.com");
+        assert_eq!(default_profile_name("s3://my-bucket/state?region=eu-west-1"), "my-bucket");
+        assert_eq!(default_profile_name("file:///var/pulumi/state"), "state");
+        assert_eq!(default_profile_name("file://~"), "local");
+        assert_eq!(default_profile_name("http://localhost:8080"), "localhost-8080");
+    }
+
+    #[test]
+    fn test_import_from_credentials_skips_known_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        fs::write(
+            &path,
+            r#"{
+                "current": "https://api.pulumi.com",
+                "accessTokens": {"https://api.pulumi.com": "pul-123"},
+                "accounts": {
+                    "https://api.pulumi.com": {"accessToken": "pul-123", "username": "me"},
+                    "s3://team-state": {"lastValidatedAt": "0001-01-01T00:00:00Z"},
+                    "s3://existing": {}
+                }
+            }"#,
+        )
+        .unwrap();
+        let existing = vec![
+            Profile::new("api.pulumi.com".to_string(), "https://other.example.com".to_string()),
+            Profile::new("mine".to_string(), "s3://existing".to_string()),
+        ];
+
+        let imported = import_from_credentials(&path, &existing).unwrap();
+
+        let pairs: Vec<_> = imported.iter().map(|p| (p.name.as_str(), p.backend.as_str())).collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("api.pulumi.com-2", "https://api.pulumi.com"),
+                ("team-state", "s3://team-state"),
+            ]
+        );
+    }
+
+    fn write_import_file(dir: &Path) -> PathBuf {
+        let path = dir.join("shared.json");
+        let shared = vec![
+            Profile::new("dev".to_string(), "s3://shared-dev".to_string()),
+            Profile::new("staging".to_string(), "s3://shared-staging".to_string()),
+        ];
+        save_pulumi_profiles(&path, &shared).unwrap();
+        path
+    }
+
+    fn setup_existing(dir: &Path) -> PathBuf {
+        let path = dir.join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://mine".to_string()), false, OPTIONS).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_only_file_sources_are_writable() {
+        let file = ProfilesSource::File(PathBuf::from("/tmp/profiles.json"));
+        assert_eq!(file.writable_path().unwrap(), Path::new("/tmp/profiles.json"));
+        assert_eq!(file.to_string(), "/tmp/profiles.json");
+
+        let remote = ProfilesSource::Remote {
+            url: "https://config.internal/profiles.json".to_string(),
+            content: "[]".to_string(),
+        };
+        assert_eq!(remote.path(), None);
+        assert!(matches!(remote.writable_path(), Err(ConfigError::RemoteReadOnly(url)) if url.starts_with("https://")));
+        assert_eq!(remote.to_string(), "https://config.internal/profiles.json");
+
+        let inline = ProfilesSource::Inline("[]".to_string());
+        assert!(matches!(inline.writable_path(), Err(ConfigError::ReadOnly)));
+        assert_eq!(inline.to_string(), PROFILES_JSON_ENV);
+    }
+
+    #[test]
+    fn test_inline_and_remote_sources_read_their_own_content() {
+        let json = r#"{"version": 1, "profiles": [{"name": "ci", "backend": "s3://ci", "group": "g"}],
+            "groups": [{"name": "g", "base": "s3://shared"}]}"#;
+        let inline = ProfilesSource::Inline(json.to_string());
+        assert_eq!(inline.load().unwrap()[0].name, "ci");
+        assert_eq!(inline.read().unwrap()[0].name, "ci");
+        assert_eq!(inline.load_groups().unwrap()[0].name, "g");
+
+        let remote = ProfilesSource::Remote {
+            url: "https://config.internal/profiles.json".to_string(),
+            content: "not json".to_string(),
+        };
+        assert!(matches!(remote.load(), Err(ConfigError::InvalidRemote(url, _)) if url.starts_with("https://")));
+        assert!(matches!(
+            ProfilesSource::Inline("not json".to_string()).load_groups(),
+            Err(ConfigError::InvalidInline(_))
+        ));
+    }
+
+    #[test]
+    fn test_export_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = setup_existing(dir.path());
+        let export_path = dir.path().join("out").join("export.json");
+
+        let source = ProfilesSource::File(path.clone());
+        assert_eq!(export_profiles(&source, &export_path).unwrap(), 1);
+
+        let exported = read_profiles_file(&export_path).unwrap();
+        assert_eq!(exported[0].name, "dev");
+        assert_eq!(exported[0].backend, "s3://mine");
+
+        // The overlay is exported too, as --list shows it
+        fs::write(
+            get_local_profiles_path(&path),
+            r#"{"version": 1, "profiles": [{"name": "mine", "backend": "file://~"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(export_profiles(&source, &export_path).unwrap(), 2);
+        assert_eq!(read_profiles_file(&export_path).unwrap()[1].name, "mine");
+    }
+
+    #[test]
+    fn test_import_profiles_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = setup_existing(dir.path());
+        let import_path = write_import_file(dir.path());
+
+        let summary = import_profiles(&path, &import_path, MergeStrategy::Skip, OPTIONS).unwrap();
+
+        assert_eq!(summary.added, vec!["staging"]);
+        assert_eq!(summary.skipped, vec!["dev"]);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].backend, "s3://mine");
+    }
+
+    #[test]
+    fn test_import_profiles_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = setup_existing(dir.path());
+        let import_path = write_import_file(dir.path());
+
+        let summary = import_profiles(&path, &import_path, MergeStrategy::Overwrite, OPTIONS).unwrap();
+
+        assert_eq!(summary.overwritten, vec!["dev"]);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].backend, "s3://shared-dev");
+    }
+
+    #[test]
+    fn test_import_profiles_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = setup_existing(dir.path());
+        let import_path = write_import_file(dir.path());
+
+        let summary = import_profiles(&path, &import_path, MergeStrategy::Rename, OPTIONS).unwrap();
+
+        assert_eq!(summary.renamed, vec![("dev".to_string(), "dev-2".to_string())]);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["dev", "dev-2", "staging"]);
+        assert_eq!(profiles[1].backend, "s3://shared-dev");
+    }
+
+    #[test]
+    fn test_import_missing_file_fails_without_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = setup_existing(dir.path());
+
+        assert!(matches!(
+            import_profiles(&path, &dir.path().join("nope.json"), MergeStrategy::Skip, OPTIONS),
+            Err(ConfigError::Io { .. })
+        ));
+        assert_eq!(read_pulumi_profiles(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_active_profile_deletion_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let current = dir.path().join("current_profile");
+
+        // Nothing active: anything may be deleted
+        assert!(!check_active_deletion(&current, &["dev"], false).unwrap());
+
+        write_current_profile(&current, "dev", None).unwrap();
+        assert!(!check_active_deletion(&current, &["prod"], false).unwrap());
+        assert!(matches!(
+            check_active_deletion(&current, &["dev"], false),
+            Err(ConfigError::ProfileActive(name)) if name == "dev"
+        ));
+        assert!(check_active_deletion(&current, &["prod", "dev"], true).unwrap());
+
+        clear_current_profile(&current).unwrap();
+        assert!(!current.exists());
+    }
+
+    #[test]
+    fn test_delete_profiles_in_bulk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        for name in ["dev", "staging", "prod"] {
+            add_profile(&path, Profile::new(name.to_string(), format!("s3://{name}")), false, OPTIONS).unwrap();
+        }
+
+        assert!(matches!(
+            delete_profiles(&path, &["dev", "missing"], OPTIONS),
+            Err(ConfigError::ProfileNotFound(name)) if name == "missing"
+        ));
+        assert_eq!(read_pulumi_profiles(&path).unwrap().len(), 3);
+
+        assert_eq!(delete_profiles(&path, &["dev", "prod"], OPTIONS).unwrap(), 2);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, "staging");
+    }
+
+    #[test]
+    fn test_name_collisions_by_case_mode() {
+        let profiles = vec![Profile::new("prod".to_string(), "s3://prod".to_string())];
+
+        assert!(matches!(
+            check_name_available(&profiles, "Prod", None, false),
+            Err(ConfigError::NameCollision { existing, .. }) if existing == "prod"
+        ));
+        assert!(matches!(
+            check_name_available(&profiles, "prod", None, false),
+            Err(ConfigError::ProfileExists(_))
+        ));
+        assert!(check_name_available(&profiles, "Prod", Some("prod"), false).is_ok());
+
+        assert!(check_name_available(&profiles, "Prod", None, true).is_ok());
+        assert!(matches!(
+            check_name_available(&profiles, "prod", None, true),
+            Err(ConfigError::ProfileExists(_))
+        ));
+        assert!(check_name_available(&profiles, "staging", None, false).is_ok());
+    }
+
+    #[test]
+    fn test_add_rename_clone_reject_case_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, OPTIONS).unwrap();
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+
+        let collides = |result: Result<()>| matches!(result, Err(ConfigError::NameCollision { .. }));
+        assert!(collides(add_profile(&path, Profile::new("PROD".to_string(), "s3://x".to_string()), false, OPTIONS)));
+        assert!(collides(rename_profile(&path, "dev", "Prod", OPTIONS)));
+        assert!(collides(clone_profile(&path, "dev", "pRod", OPTIONS)));
+
+        // Changing only the casing of a profile's own name is fine, and is kept
+        rename_profile(&path, "dev", "Dev", OPTIONS).unwrap();
+        assert!(read_pulumi_profiles(&path).unwrap().iter().any(|p| p.name == "Dev"));
+
+        let case_sensitive = WriteOptions { case_sensitive: true, ..*OPTIONS };
+        add_profile(&path, Profile::new("PROD".to_string(), "s3://x".to_string()), false, &case_sensitive).unwrap();
+        clone_profile(&path, "Dev", "dev", &case_sensitive).unwrap();
+        assert!(check_new_profile_name(&read_pulumi_profiles(&path).unwrap(), "Prod", true).is_ok());
+        assert!(check_new_profile_name(&read_pulumi_profiles(&path).unwrap(), "Prod", false).is_err());
+    }
+
+
+    #[test]
+    fn test_merge_profiles_overrides_by_name() {
+        let profile = |name: &str, backend: &str| Profile::new(name.to_string(), backend.to_string());
+        let base = vec![profile("dev", "s3://dev"), profile("prod", "s3://prod")];
+        let overlay = vec![profile("mine", "file:///tmp/mine"), profile("dev", "s3://my-dev")];
+
+        let merged = merge_profiles(base, overlay);
+        let pairs: Vec<(&str, &str)> = merged.iter().map(|p| (p.name.as_str(), p.backend.as_str())).collect();
+        assert_eq!(pairs, [("dev", "s3://my-dev"), ("prod", "s3://prod"), ("mine", "file:///tmp/mine")]);
+    }
+
+    #[test]
+    fn test_local_overlay_is_read_but_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let local_path = get_local_profiles_path(&path);
+        assert_eq!(local_path, dir.path().join("profiles.local.json"));
+
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        add_profile(&local_path, Profile::new("dev".to_string(), "s3://my-dev".to_string()), false, OPTIONS).unwrap();
+        add_profile(&local_path, Profile::new("mine".to_string(), "s3://mine".to_string()), false, OPTIONS).unwrap();
+
+        let merged = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].backend, "s3://my-dev");
+
+        // Writes to the base file leave the overlay's entries out of it
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, OPTIONS).unwrap();
+        assert_eq!(load_pulumi_profiles(&path).unwrap().len(), 2);
+        mark_profile_used(&path, "mine").unwrap();
+        assert!(load_pulumi_profiles(&local_path).unwrap()[1].last_used.is_some());
+        assert!(load_pulumi_profiles(&path).unwrap().iter().all(|p| p.name != "mine"));
+
+        assert!(matches!(check_not_overlay_only(&path, "mine"), Err(ConfigError::OnlyInOverlay(name)) if name == "mine"));
+        assert!(check_not_overlay_only(&path, "dev").is_ok());
+        assert!(check_not_overlay_only(&path, "missing").is_ok());
+        assert!(check_not_overlay_only(&local_path, "mine").is_ok());
+    }
+    #[test]
+    fn test_find_duplicate_names_and_dedupe() {
+        let profile = |name: &str, backend: &str| Profile::new(name.to_string(), backend.to_string());
+        let profiles = vec![
+            profile("dev", "s3://dev"),
+            profile("prod", "s3://prod"),
+            profile("dev", "s3://dev-copy"),
+            profile("dev", "s3://dev-copy-2"),
+        ];
+        assert_eq!(find_duplicate_names(&profiles), vec!["dev".to_string()]);
+        assert!(find_duplicate_names(&profiles[..2]).is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        save_pulumi_profiles(&path, &profiles).unwrap();
+
+        assert_eq!(dedupe_profiles(&path, OPTIONS).unwrap(), 2);
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].backend, "s3://dev");
+    }
+
+    #[test]
+    fn test_replace_in_backends_rewrites_partial_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let profile = |name: &str, backend: &str| Profile::new(name.to_string(), backend.to_string());
+        let profiles = vec![
+            profile("dev", "s3://old-state/dev"),
+            profile("prod", "s3://old-state/prod?region=old-state"),
+            profile("local", "file:///tmp/state"),
+        ];
+        save_pulumi_profiles(&path, &profiles).unwrap();
+
+        let plan = plan_backend_replacements(&profiles, "old-state", "new-state");
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan[1].after, "s3://new-state/prod?region=new-state");
+
+        assert_eq!(replace_in_backends(&path, "old-state", "new-state", false, OPTIONS).unwrap(), 2);
+        let backends: Vec<String> = read_pulumi_profiles(&path).unwrap().into_iter().map(|p| p.backend).collect();
+        assert_eq!(
+            backends,
+            ["s3://new-state/dev", "s3://new-state/prod?region=new-state", "file:///tmp/state"]
+        );
+    }
+
+    #[test]
+    fn test_replace_in_backends_without_a_match_or_with_an_invalid_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+
+        assert_eq!(replace_in_backends(&path, "nowhere", "x", false, OPTIONS).unwrap(), 0);
+        assert!(plan_backend_replacements(&read_pulumi_profiles(&path).unwrap(), "", "x").is_empty());
+        assert!(matches!(replace_in_backends(&path, "", "x", false, OPTIONS), Err(ConfigError::InvalidBackend(_))));
+
+        // One bad rewrite leaves every profile untouched
+        assert!(replace_in_backends(&path, "s3://", "ftp://", false, OPTIONS).is_err());
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[0].backend, "s3://dev");
+        assert_eq!(replace_in_backends(&path, "s3://", "ftp://", true, OPTIONS).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_parse_error_points_at_location_and_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(&path, "[\n  {\"name\": \"dev\",\n}\n]").unwrap();
+
+        let err = read_pulumi_profiles(&path).unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }));
+        let err = err.to_string();
+        assert!(err.contains("line 3"), "{err}");
+        assert!(err.contains("--repair"));
+
+        let backup = dir.path().join("profiles.json.bak");
+        assert_eq!(repair_profiles(&path).unwrap(), RepairOutcome::Reset { backup: backup.clone() });
+        assert!(read_pulumi_profiles(&path).unwrap().is_empty());
+        assert!(fs::read_to_string(&backup).unwrap().contains("\"dev\""));
+        assert_eq!(repair_profiles(&path).unwrap(), RepairOutcome::Healthy);
+    }
+
+    #[test]
+    fn test_repair_wraps_single_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(&path, r#"{"name": "dev", "backend": "s3://dev"}"#).unwrap();
+
+        let err = read_pulumi_profiles(&path).unwrap_err();
+        assert!(matches!(err, ConfigError::InvalidFile(_)));
+        assert!(err.to_string().contains("single profile object"), "{err}");
+
+        assert!(matches!(repair_profiles(&path).unwrap(), RepairOutcome::Wrapped { .. }));
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].backend, "s3://dev");
+    }
+
+    #[test]
+    fn test_legacy_wrapped_profiles_are_read_and_canonicalised() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        fs::write(&path, r#"[{"name": "dev", "backend": "s3://dev"}]"#).unwrap();
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[0].name, "dev");
+
+        fs::write(&path, r#"{"profiles": [{"name": "dev", "backend": "s3://dev"}]}"#).unwrap();
+        assert_eq!(read_pulumi_profiles(&path).unwrap()[0].name, "dev");
+
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, OPTIONS).unwrap();
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], PROFILES_FILE_VERSION);
+        assert_eq!(saved["profiles"].as_array().map(Vec::len), Some(2));
+    }
+
+    #[test]
+    fn test_migrate_v0_to_v1() {
+        let v0 = serde_json::json!([{"name": "dev", "backend": "s3://dev"}]);
+        let file = migrate(0, v0).unwrap();
+        assert_eq!(file.version, 1);
+        assert_eq!(file.profiles[0].name, "dev");
+
+        let v1 = serde_json::json!({"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}]});
+        assert_eq!(migrate(1, v1).unwrap().profiles.len(), 1);
+
+        let err = migrate(PROFILES_FILE_VERSION + 1, serde_json::json!({})).unwrap_err();
+        assert!(matches!(err, ConfigError::UnsupportedVersion(v) if v == PROFILES_FILE_VERSION + 1));
+        assert!(err.to_string().contains("newer"));
+    }
+
+    #[test]
+    fn test_mutations_back_up_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let backup = dir.path().join("profiles.json.bak");
+
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        assert!(!backup.exists(), "first creation has nothing to back up");
+
+        let before = fs::read_to_string(&path).unwrap();
+        edit_profile(&path, "dev", "s3://dev-2".to_string(), false, OPTIONS).unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), before);
+
+        mark_profile_used(&path, "dev").unwrap();
+        assert_eq!(fs::read_to_string(&backup).unwrap(), before);
+    }
+
+    #[test]
+    fn test_backup_rotation_keeps_last_n() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        for generation in 0..5 {
+            fs::write(&path, generation.to_string()).unwrap();
+            backup_profiles(&path, 3).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(dir.path().join("profiles.json.bak")).unwrap(), "4");
+        assert_eq!(fs::read_to_string(dir.path().join("profiles.json.bak.1")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(dir.path().join("profiles.json.bak.2")).unwrap(), "2");
+        assert!(!dir.path().join("profiles.json.bak.3").exists());
+    }
+
+    #[test]
+    fn test_backups_follow_write_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let backups = |n: usize| WriteOptions { backups: n, ..*OPTIONS };
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+
+        edit_profile(&path, "dev", "s3://dev-2".to_string(), false, &backups(0)).unwrap();
+        assert!(!dir.path().join("profiles.json.bak").exists());
+
+        for backend in ["s3://dev-3", "s3://dev-4", "s3://dev-5"] {
+            edit_profile(&path, "dev", backend.to_string(), false, &backups(2)).unwrap();
+        }
+        assert!(dir.path().join("profiles.json.bak.1").exists());
+        assert!(!dir.path().join("profiles.json.bak.2").exists());
+    }
+
+    #[test]
+    fn test_dry_run_validates_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        add_profile(&path, Profile::new("dev".to_string(), "s3://dev".to_string()), false, OPTIONS).unwrap();
+        let before = fs::read_to_string(&path).unwrap();
+        let dry_run = WriteOptions { dry_run: true, ..*OPTIONS };
+
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, &dry_run).unwrap();
+        assert_eq!(delete_profiles(&path, &["dev"], &dry_run).unwrap(), 1);
+        assert!(matches!(
+            rename_profile(&path, "missing", "other", &dry_run),
+            Err(ConfigError::ProfileNotFound(_))
+        ));
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+        assert!(!dir.path().join("profiles.json.bak").exists());
+    }
+
+    #[test]
+    fn test_clone_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let mut dev = Profile::new("dev".to_string(), "s3://dev".to_string());
+        dev.tags = vec!["team".to_string()];
+        dev.last_used = Some(42);
+        add_profile(&path, dev, false, OPTIONS).unwrap();
+        add_profile(&path, Profile::new("prod".to_string(), "s3://prod".to_string()), false, OPTIONS).unwrap();
+
+        clone_profile(&path, "dev", "dev-eu", OPTIONS).unwrap();
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        let clone = profiles.iter().find(|p| p.name == "dev-eu").unwrap();
+        assert_eq!(clone.backend, "s3://dev");
+        assert_eq!(clone.tags, vec!["team".to_string()]);
+        assert_eq!(clone.last_used, None);
+
+        assert!(matches!(clone_profile(&path, "missing", "other", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+        assert!(matches!(clone_profile(&path, "dev", "prod", OPTIONS), Err(ConfigError::ProfileExists(_))));
+        assert_eq!(read_pulumi_profiles(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_backend_suggestions_offer_hosts_of_the_scheme() {
+        let profile = |name: &str, backend: &str, last_used: Option<i64>| Profile {
+            last_used,
+            ..Profile::new(name.to_string(), backend.to_string())
+        };
+        let profiles = [
+            profile("dev", "s3://dev-state/app?region=eu-west-1", None),
+            profile("prod", "s3://prod-state/app", Some(200)),
+            profile("prod-2", "s3://prod-state/other", Some(100)),
+            profile("gcp", "gs://gcp-state", Some(300)),
+            profile("s3ish", "s3x://not-s3", None),
+            profile("member", "suffix-only", None),
+        ];
+
+        assert_eq!(backend_suggestions(&profiles, "s3"), ["prod-state", "dev-state"]);
+        assert_eq!(backend_suggestions(&profiles, "gs"), ["gcp-state"]);
+        assert!(backend_suggestions(&profiles, "azblob").is_empty());
+    }
+
+    #[test]
+    fn test_group_by_scheme() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("local".to_string(), "file://./state".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+            Profile::new("odd".to_string(), "custom-backend".to_string()),
+            Profile::new("gcp".to_string(), "gs://state".to_string()),
+        ];
+
+        let groups: Vec<(String, Vec<String>)> = group_by_scheme(profiles)
+            .into_iter()
+            .map(|(scheme, members)| (scheme, members.into_iter().map(|p| p.name).collect()))
+            .collect();
+
+        assert_eq!(
+            groups,
+            vec![
+                ("s3".to_string(), vec!["dev".to_string(), "prod".to_string()]),
+                ("file".to_string(), vec!["local".to_string()]),
+                ("other".to_string(), vec!["odd".to_string()]),
+                ("gs".to_string(), vec!["gcp".to_string()]),
+            ]
+        );
+        assert!(group_by_scheme(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_load_and_failed_updates_leave_disk_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        assert!(load_pulumi_profiles(&path).unwrap().is_empty());
+        assert!(!path.exists());
+
+        assert!(matches!(delete_profile(&path, "missing", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+        assert!(!path.exists());
+
+        assert!(read_pulumi_profiles(&path).unwrap().is_empty());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_resolve_profile() {
+        let profiles = vec![
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("development".to_string(), "s3://development".to_string()),
+            Profile::new("Production".to_string(), "s3://prod".to_string()),
+            Profile::new("staging-eu".to_string(), "s3://eu".to_string()),
+            Profile::new("staging-us".to_string(), "s3://us".to_string()),
+        ];
+
+        assert_eq!(resolve_profile(&profiles, "dev"), Resolution::Exact(&profiles[0]));
+        assert_eq!(resolve_profile(&profiles, "prod"), Resolution::UniquePrefix(&profiles[2]));
+        assert_eq!(resolve_profile(&profiles, "devel"), Resolution::UniquePrefix(&profiles[1]));
+        assert_eq!(
+            resolve_profile(&profiles, "staging"),
+            Resolution::Ambiguous(vec![&profiles[3], &profiles[4]])
+        );
+        assert_eq!(resolve_profile(&profiles, "qa"), Resolution::NotFound);
+    }
+
+    #[test]
+    fn test_expand_backend() {
+        let lookup = |name: &str| match name {
+            "BUCKET" => Some("state-bucket".to_string()),
+            "REGION" => Some("eu-west-1".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            expand_backend_with("s3://$BUCKET/infra?region=${REGION}", lookup).unwrap(),
+            "s3://state-bucket/infra?region=eu-west-1"
+        );
+        assert_eq!(expand_backend_with("s3://${BUCKET}suffix", lookup).unwrap(), "s3://state-bucketsuffix");
+        assert_eq!(expand_backend_with("file://./state", lookup).unwrap(), "file://./state");
+        assert_eq!(expand_backend_with("s3://cost$/a$1", lookup).unwrap(), "s3://cost$/a$1");
+
+        let err = expand_backend_with("s3://$MISSING/infra", lookup).unwrap_err();
+        assert!(matches!(&err, ConfigError::UnsetVariable { name, .. } if name == "MISSING"));
+        assert!(err.to_string().contains("$MISSING"), "{err}");
+        assert!(matches!(expand_backend_with("s3://${BUCKET", lookup), Err(ConfigError::InvalidBackend(_))));
+    }
+
+    #[test]
+    fn test_backend_matches_env() {
+        let profile = Profile::new("dev".to_string(), "s3://dev-state".to_string());
+
+        assert!(backend_matches_env(&profile, None), "nothing exported, nothing stale");
+        assert!(backend_matches_env(&profile, Some("")));
+        assert!(backend_matches_env(&profile, Some("s3://dev-state")));
+        assert!(backend_matches_env(&profile, Some("s3://dev-state/")));
+        assert!(!backend_matches_env(&profile, Some("s3://prod-state")));
+    }
+
+    #[test]
+    fn test_only_one_profile_is_pinned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        for name in ["dev", "staging", "prod"] {
+            add_profile(&path, Profile::new(name.to_string(), format!("s3://{name}")), false, OPTIONS).unwrap();
+        }
+        let pinned = |path: &Path| -> Vec<String> {
+            read_pulumi_profiles(path)
+                .unwrap()
+                .into_iter()
+                .filter(|p| p.pinned)
+                .map(|p| p.name)
+                .collect()
+        };
+
+        pin_profile(&path, "dev", OPTIONS).unwrap();
+        assert_eq!(pinned(&path), vec!["dev"]);
+
+        pin_profile(&path, "prod", OPTIONS).unwrap();
+        assert_eq!(pinned(&path), vec!["prod"]);
+
+        clone_profile(&path, "prod", "prod-copy", OPTIONS).unwrap();
+        assert_eq!(pinned(&path), vec!["prod"]);
+
+        assert!(matches!(pin_profile(&path, "missing", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+        assert_eq!(pinned(&path), vec!["prod"]);
+
+        unpin_profile(&path, "prod", OPTIONS).unwrap();
+        assert!(pinned(&path).is_empty());
+    }
+
+    #[test]
+    fn test_only_one_profile_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        for name in ["dev", "staging", "prod"] {
+            add_profile(&path, Profile::new(name.to_string(), format!("s3://{name}")), false, OPTIONS).unwrap();
+        }
+        let defaults = |path: &Path| -> Vec<String> {
+            read_pulumi_profiles(path)
+                .unwrap()
+                .into_iter()
+                .filter(|p| p.default)
+                .map(|p| p.name)
+                .collect()
+        };
+        assert!(default_profile(&read_pulumi_profiles(&path).unwrap()).is_none());
+
+        set_default_profile(&path, "dev", OPTIONS).unwrap();
+        set_default_profile(&path, "staging", OPTIONS).unwrap();
+        assert_eq!(defaults(&path), vec!["staging"]);
+
+        clone_profile(&path, "staging", "staging-copy", OPTIONS).unwrap();
+        assert!(matches!(set_default_profile(&path, "missing", OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+        assert_eq!(defaults(&path), vec!["staging"]);
+        assert_eq!(default_profile(&read_pulumi_profiles(&path).unwrap()).unwrap().name, "staging");
+    }
+
+    #[test]
+    fn test_moving_token_to_keyring_drops_plaintext() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let mut profile = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        profile.access_token = Some("pul-123".to_string());
+        add_profile(&path, profile, false, OPTIONS).unwrap();
+
+        set_token_in_keyring(&path, "cloud", true, OPTIONS).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(r#""access_token_in_keyring": true"#));
+        assert!(!content.contains("pul-123"));
+
+        set_token_in_keyring(&path, "cloud", false, OPTIONS).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("access_token"));
+        assert!(matches!(set_token_in_keyring(&path, "missing", true, OPTIONS), Err(ConfigError::ProfileNotFound(_))));
+    }
+
+    #[test]
+    fn test_json_schema_describes_profiles_file() {
+        let schema: Value = serde_json::from_str(&profiles_json_schema()).unwrap();
+
+        assert_eq!(schema["required"], serde_json::json!(["version", "profiles"]));
+        let profile = &schema["$defs"]["Profile"];
+        assert_eq!(profile["required"], serde_json::json!(["name", "backend"]));
+        for field in ["description", "tags", "access_token", "passphrase_file", "pinned", "default"] {
+            assert!(profile["properties"].get(field).is_some(), "schema misses {field}");
+        }
+        assert_eq!(profiles_json_schema(), profiles_json_schema());
+    }
+
+    #[test]
+    fn test_file_backend_path() {
+        assert_eq!(file_backend_path("file:///var/pulumi/state"), Some(PathBuf::from("/var/pulumi/state")));
+        assert_eq!(file_backend_path("file://./state"), Some(PathBuf::from("./state")));
+        assert_eq!(file_backend_path("file://state/dev?no_legacy=true"), Some(PathBuf::from("state/dev")));
+        assert_eq!(file_backend_path("file://~/state"), dirs::home_dir().map(|home| home.join("state")));
+        assert_eq!(file_backend_path("file://"), None);
+        assert_eq!(file_backend_path("s3://bucket"), None);
+    }
+
+    #[test]
+    fn test_resolve_relative_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+        fs::create_dir(cwd.join("state")).unwrap();
+
+        let resolved = resolve_file_backend("file://./state", &cwd).unwrap();
+        assert_eq!(resolved, format!("file://{}", cwd.join("state").display()));
+        assert_eq!(
+            resolve_file_backend("file://state?no_legacy=true", &cwd).unwrap(),
+            format!("file://{}?no_legacy=true", cwd.join("state").display())
+        );
+        // Not created yet, so only folded
+        assert_eq!(
+            resolve_file_backend("file://./new/../later", &cwd).unwrap(),
+            format!("file://{}", cwd.join("later").display())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_file_backend_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+        fs::create_dir(cwd.join("real")).unwrap();
+        std::os::unix::fs::symlink(cwd.join("real"), cwd.join("link")).unwrap();
+
+        assert_eq!(
+            resolve_file_backend("file://link", &cwd).unwrap(),
+            format!("file://{}", cwd.join("real").display())
+        );
+    }
+
+    #[test]
+    fn test_resolve_file_backend_leaves_others_alone() {
+        let cwd = Path::new("/somewhere");
+        let untouched = [
+            "file:///var/pulumi/state",
+            "file://~",
+            "file://~/state",
+            "file://",
+            "s3://bucket/state",
+            "https://api.pulumi.com",
+        ];
+        for url in untouched {
+            assert_eq!(resolve_file_backend(url, cwd).unwrap(), url);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use pulumi_profile_selector::config::{
+    Profile, ProfilesSource, find_duplicate_names, read_current_profile, resolve_group_backends,
+    validate_backend_url,
+};
+use pulumi_profile_selector::shell::{ShellKind, UnknownShell};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Ok,
+    Warn,
+    /// Makes `--doctor` exit non-zero
+    Error,
+}
+
+/// One line of the `--doctor` report.
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Ok => "OK",
+            Severity::Warn => "WARN",
+            Severity::Error => "ERROR",
+        };
+        write!(f, "{label:<5} {}", self.message)
+    }
+}
+
+/// Checks the setup for problems that otherwise only show up as confusing
+/// failures later: an unreadable profiles file, duplicate names, unusable
+/// backends, a `current_profile` naming a profile that's gone, and a shell
+/// `--current` can't detect. Nothing is created or repaired.
+pub fn diagnose(
+    profiles_source: &ProfilesSource,
+    current_profile_path: &Path,
+    shell: Result<ShellKind, UnknownShell>,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if let Some(profiles) = check_profiles_file(profiles_source, &mut findings) {
+        check_profiles(profiles_source, &profiles, &mut findings);
+        check_current_profile(current_profile_path, &profiles, &mut findings);
+    }
+
+    findings.push(match shell {
+        Ok(kind) => Finding::new(Severity::Ok, format!("Shell for --current: {}", format!("{kind:?}").to_lowercase())),
+        Err(e) => Finding::new(Severity::Warn, format!("{e}; --current needs --shell or --assume-posix")),
+    });
+
+    findings
+}
+
+/// Reads the profiles, or `None` when they can't be, which makes the checks
+/// that depend on them pointless.
+fn check_profiles_file(source: &ProfilesSource, findings: &mut Vec<Finding>) -> Option<Vec<Profile>> {
+    let exists = source.path().is_none_or(Path::exists);
+    if !exists {
+        findings.push(Finding::new(Severity::Warn, format!("{source} does not exist; run --add to create it")));
+    }
+
+    match source.load() {
+        Ok(profiles) => {
+            if exists {
+                findings.push(Finding::new(Severity::Ok, format!("{source} parses ({} profile(s))", profiles.len())));
+            }
+            Some(profiles)
+        }
+        Err(e) => {
+            findings.push(Finding::new(Severity::Error, format!("{source} can't be read: {e}")));
+            None
+        }
+    }
+}
+
+fn check_profiles(profiles_source: &ProfilesSource, profiles: &[Profile], findings: &mut Vec<Finding>) {
+    let duplicates = find_duplicate_names(profiles);
+    if duplicates.is_empty() {
+        findings.push(Finding::new(Severity::Ok, "No duplicate profile names"));
+    } else {
+        findings.push(Finding::new(
+            Severity::Warn,
+            format!(
+                "Duplicate profile names only use the first entry: {} (run --dedupe)",
+                duplicates.join(", ")
+            ),
+        ));
+    }
+
+    // Group members are checked by the backend they activate
+    let groups = profiles_source.load_groups().unwrap_or_default();
+    let mut all_valid = true;
+    for profile in resolve_group_backends(profiles.to_vec(), &groups) {
+        if profile.backend.trim().is_empty() {
+            all_valid = false;
+            findings.push(Finding::new(
+                Severity::Error,
+                format!("Profile '{}' has an empty backend (fix it with --edit {})", profile.name, profile.name),
+            ));
+        } else if let Err(e) = validate_backend_url(&profile.backend) {
+            all_valid = false;
+            findings.push(Finding::new(Severity::Warn, format!("Profile '{}': {e}", profile.name)));
+        }
+    }
+    if all_valid {
+        findings.push(Finding::new(Severity::Ok, "Every backend uses a scheme Pulumi understands"));
+    }
+}
+
+fn check_current_profile(current_profile_path: &Path, profiles: &[Profile], findings: &mut Vec<Finding>) {
+    findings.push(match read_current_profile(current_profile_path) {
+        Ok(None) => Finding::new(Severity::Ok, "No active profile"),
+        Ok(Some(name)) if profiles.iter().any(|p| p.name == name) => {
+            Finding::new(Severity::Ok, format!("Active profile '{name}' exists"))
+        }
+        Ok(Some(name)) => Finding::new(
+            Severity::Error,
+            format!("current_profile names '{name}', which isn't a profile (run --deactivate to clear it)"),
+        ),
+        Err(e) => Finding::new(Severity::Error, format!("current_profile can't be read: {e}")),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn severities(findings: &[Finding]) -> Vec<Severity> {
+        findings.iter().map(|f| f.severity).collect()
+    }
+
+    #[test]
+    fn test_healthy_setup_is_all_ok() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_path = dir.path().join("profiles.json");
+        let current_path = dir.path().join("current_profile");
+        fs::write(&profiles_path, r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}]}"#).unwrap();
+        fs::write(&current_path, "dev").unwrap();
+
+        let findings = diagnose(&ProfilesSource::File(profiles_path), &current_path, Ok(ShellKind::Zsh));
+        assert!(findings.iter().all(|f| f.severity == Severity::Ok), "{findings:?}");
+        assert_eq!(findings.last().unwrap().to_string(), "OK    Shell for --current: zsh");
+    }
+
+    #[test]
+    fn test_problems_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_path = dir.path().join("profiles.json");
+        let current_path = dir.path().join("current_profile");
+        fs::write(
+            &profiles_path,
+            r#"{"version": 1, "profiles": [
+                {"name": "dev", "backend": "s3://dev"},
+                {"name": "dev", "backend": "s3://dev-2"},
+                {"name": "odd", "backend": "ftp://odd"},
+                {"name": "blank", "backend": ""}
+            ]}"#,
+        )
+        .unwrap();
+        fs::write(&current_path, "gone").unwrap();
+
+        let findings = diagnose(&ProfilesSource::File(profiles_path), &current_path, Err(UnknownShell::Unset));
+        assert_eq!(
+            severities(&findings),
+            [
+                Severity::Ok,
+                Severity::Warn,
+                Severity::Warn,
+                Severity::Error,
+                Severity::Error,
+                Severity::Warn
+            ]
+        );
+        assert!(findings[1].message.contains("dev"));
+        assert!(findings[2].message.contains("'odd'"));
+        assert!(findings[4].message.contains("'gone'"));
+    }
+
+    #[test]
+    fn test_unreadable_file_skips_dependent_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_path = dir.path().join("profiles.json");
+        fs::write(&profiles_path, "{not json").unwrap();
+
+        let current_path = dir.path().join("current_profile");
+        let findings = diagnose(&ProfilesSource::File(profiles_path), &current_path, Ok(ShellKind::Bash));
+        assert_eq!(severities(&findings), [Severity::Error, Severity::Ok]);
+
+        let missing = diagnose(&ProfilesSource::File(dir.path().join("none.json")), &current_path, Ok(ShellKind::Bash));
+        assert_eq!(missing[0].severity, Severity::Warn);
+        assert!(!dir.path().join("none.json").exists(), "--doctor must not create files");
+    }
+}
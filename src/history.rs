@@ -0,0 +1,229 @@
+//! A short log of recent activations, kept in history.json next to
+//! profiles.json, and the opt-in audit log of who activated what.
+
+use crate::config::{ConfigError, IoContext, Result, get_pulumi_home, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Oldest entries are dropped once the history grows past this.
+pub const MAX_HISTORY_ENTRIES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub name: String,
+    /// Seconds since the Unix epoch
+    pub activated_at: i64,
+}
+
+pub fn get_history_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("history.json"))
+}
+
+/// Appends an activation of `name`, keeping only the newest
+/// `MAX_HISTORY_ENTRIES` entries.
+pub fn record_activation(history_path: &Path, name: &str) -> Result<()> {
+    append_entry(
+        history_path,
+        HistoryEntry {
+            name: name.to_string(),
+            activated_at: now(),
+        },
+    )
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Appends `TIMESTAMP USER PROFILE BACKEND` to the audit log at `audit_path`,
+/// creating it (and its directory) if needed. A deactivation has no backend
+/// and is logged with `-` in its place, as is a profile that isn't known.
+pub fn append_audit_entry(audit_path: &Path, profile: Option<&str>, backend: Option<&str>) -> Result<()> {
+    let user = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let line = format_audit_line(now(), &user, profile, backend);
+
+    if let Some(parent) = audit_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+    // A single append of the whole line keeps concurrent writers from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .io_context(|| format!("Failed to append to audit log: {audit_path:?}"))
+}
+
+fn format_audit_line(secs: i64, user: &str, profile: Option<&str>, backend: Option<&str>) -> String {
+    // Whitespace would shift the columns, so it's replaced in every field
+    let field = |value: Option<&str>| match value.filter(|v| !v.is_empty()) {
+        Some(value) => value.replace(char::is_whitespace, "_"),
+        None => "-".to_string(),
+    };
+    format!(
+        "{} {} {} {}\n",
+        format_iso8601(secs),
+        field(Some(user)),
+        field(profile),
+        field(backend)
+    )
+}
+
+/// Formats a Unix timestamp as ISO 8601 in UTC, e.g. `2025-12-31T23:59:59Z`.
+pub fn format_iso8601(secs: i64) -> String {
+    format!("{}Z", format_timestamp(secs).replacen(' ', "T", 1))
+}
+
+fn append_entry(history_path: &Path, entry: HistoryEntry) -> Result<()> {
+    log::debug!("Recording activation of '{}' in {}", entry.name, history_path.display());
+    let mut entries = read_entries(history_path)?;
+    entries.push(entry);
+    if entries.len() > MAX_HISTORY_ENTRIES {
+        entries.drain(..entries.len() - MAX_HISTORY_ENTRIES);
+    }
+
+    if let Some(parent) = history_path.parent() {
+        fs::create_dir_all(parent).io_context(|| format!("Failed to create directory {parent:?}"))?;
+    }
+
+    let content = serde_json::to_string_pretty(&entries).map_err(|source| ConfigError::Parse {
+        context: "Failed to serialize history".to_string(),
+        source,
+    })?;
+    write_atomic(history_path, content.as_bytes())
+        .io_context(|| format!("Failed to write history file: {history_path:?}"))
+}
+
+/// Returns the last `n` activations, newest first. A missing file is an
+/// empty history.
+pub fn read_history(history_path: &Path, n: usize) -> Result<Vec<HistoryEntry>> {
+    let entries = read_entries(history_path)?;
+    Ok(entries.into_iter().rev().take(n).collect())
+}
+
+fn read_entries(history_path: &Path) -> Result<Vec<HistoryEntry>> {
+    if !history_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    log::debug!("Reading history from {}", history_path.display());
+    let content = fs::read_to_string(history_path)
+        .io_context(|| format!("Failed to read history file: {history_path:?}"))?;
+    serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+        context: format!("Failed to parse history file: {history_path:?}"),
+        source,
+    })
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD HH:MM:SS` in UTC.
+pub fn format_timestamp(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil-from-days, after Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, activated_at: i64) -> HistoryEntry {
+        HistoryEntry {
+            name: name.to_string(),
+            activated_at,
+        }
+    }
+
+    #[test]
+    fn test_append_and_read_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("history.json");
+        assert!(read_history(&path, 10).unwrap().is_empty());
+
+        append_entry(&path, entry("dev", 1)).unwrap();
+        append_entry(&path, entry("prod", 2)).unwrap();
+        append_entry(&path, entry("dev", 3)).unwrap();
+
+        assert_eq!(read_history(&path, 10).unwrap(), vec![entry("dev", 3), entry("prod", 2), entry("dev", 1)]);
+        assert_eq!(read_history(&path, 1).unwrap(), vec![entry("dev", 3)]);
+
+        record_activation(&path, "staging").unwrap();
+        assert_eq!(read_history(&path, 1).unwrap()[0].name, "staging");
+    }
+
+    #[test]
+    fn test_history_is_trimmed_to_newest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+
+        for i in 0..MAX_HISTORY_ENTRIES as i64 + 5 {
+            append_entry(&path, entry(&format!("p{i}"), i)).unwrap();
+        }
+
+        let history = read_history(&path, usize::MAX).unwrap();
+        assert_eq!(history.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history.first().unwrap().activated_at, MAX_HISTORY_ENTRIES as i64 + 4);
+        assert_eq!(history.last().unwrap().activated_at, 5);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56");
+        assert_eq!(format_timestamp(1_767_225_599), "2025-12-31 23:59:59");
+        assert_eq!(format_iso8601(1_767_225_599), "2025-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_audit_line_format() {
+        assert_eq!(
+            format_audit_line(951_827_696, "alice", Some("dev"), Some("s3://state-dev")),
+            "2000-02-29T12:34:56Z alice dev s3://state-dev\n"
+        );
+        assert_eq!(
+            format_audit_line(0, "Jane Doe", Some("dev"), None),
+            "1970-01-01T00:00:00Z Jane_Doe dev -\n"
+        );
+        assert_eq!(format_audit_line(0, "", None, None), "1970-01-01T00:00:00Z - - -\n");
+    }
+
+    #[test]
+    fn test_audit_log_is_created_and_appended() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logs").join("audit.log");
+
+        append_audit_entry(&path, Some("dev"), Some("s3://dev")).unwrap();
+        append_audit_entry(&path, Some("dev"), None).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<Vec<&str>> = content.lines().map(|line| line.split(' ').collect()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|fields| fields.len() == 4 && fields[0].ends_with('Z')));
+        assert_eq!(lines[0][2..], ["dev", "s3://dev"]);
+        assert_eq!(lines[1][2..], ["dev", "-"]);
+    }
+}
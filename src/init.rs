@@ -0,0 +1,85 @@
+/// Shells `--init` can write a wrapper function for.
+pub const INIT_SHELLS: &[&str] = &["bash", "zsh", "fish", "nu"];
+
+/// Name of the wrapper function the init scripts define.
+const FUNCTION_NAME: &str = "pps";
+
+/// Builds a function for `shell` that runs `bin --current` with its arguments
+/// and applies the printed commands to the calling shell. Output is only
+/// applied when the binary succeeds, so a cancelled selection changes nothing.
+pub fn init_script(shell: &str, bin: &str) -> String {
+    let name = FUNCTION_NAME;
+    match shell {
+        // The shell is passed explicitly since $SHELL may name the login shell instead
+        "bash" | "zsh" => format!(
+            r#"{name}() {{
+    local output
+    output="$(command {bin} --current --shell {shell} "$@")" || return
+    eval "$output"
+}}
+"#
+        ),
+        "fish" => format!(
+            r#"function {name} --description 'Switch Pulumi profiles in this shell'
+    set -l output (command {bin} --current --shell fish $argv)
+    or return
+    printf '%s\n' $output | source
+end
+"#
+        ),
+        // Nushell has no eval, so the wrapper parses the one statement per line it prints
+        "nu" => format!(
+            r#"def --env --wrapped {name} [...args] {{
+    let lines = (^{bin} --current --shell nu ...$args | lines)
+    let sets = ($lines | parse --regex r##'^\$env\.(?P<name>\w+) = r#+'(?P<value>.*)'#+$'##)
+    if ($sets | is-not-empty) {{
+        load-env ($sets | reduce -f {{}} {{|it, acc| $acc | upsert $it.name $it.value}})
+    }}
+    let unsets = ($lines | parse 'hide-env -i {{name}}' | get name)
+    if ($unsets | is-not-empty) {{
+        hide-env -i ...$unsets
+    }}
+}}
+"#
+        ),
+        _ => unreachable!("clap restricts --init values"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIN: &str = "pulumi-profile-selector";
+
+    #[test]
+    fn test_posix_shells_eval_current_output() {
+        for shell in ["bash", "zsh"] {
+            let script = init_script(shell, BIN);
+            assert!(script.starts_with("pps() {\n"), "{shell}");
+            assert!(script.contains(&format!(r#"command {BIN} --current --shell {shell} "$@")" || return"#)));
+            assert!(script.contains(r#"eval "$output""#));
+            assert!(script.ends_with("}\n"));
+        }
+    }
+
+    #[test]
+    fn test_fish_function_sources_output() {
+        let script = init_script("fish", BIN);
+        assert!(script.starts_with("function pps "));
+        assert!(script.contains(&format!("(command {BIN} --current --shell fish $argv)")));
+        assert!(script.contains("or return\n"));
+        assert!(script.contains("| source"));
+        assert!(script.ends_with("end\n"));
+    }
+
+    #[test]
+    fn test_nu_function_applies_each_line() {
+        let script = init_script("nu", BIN);
+        assert!(script.starts_with("def --env --wrapped pps [...args] {"));
+        assert!(script.contains(&format!("(^{BIN} --current --shell nu ...$args | lines)")));
+        assert!(script.contains(r#"parse --regex r##'^\$env\.(?P<name>\w+) = r#+'(?P<value>.*)'#+$'##"#));
+        assert!(script.contains("load-env"));
+        assert!(script.contains("hide-env -i ...$unsets"));
+    }
+}
@@ -0,0 +1,33 @@
+//! Profile storage and shell integration behind the `pulumi-profile-selector`
+//! CLI, for programs that manage Pulumi profiles themselves.
+//!
+//! ```no_run
+//! use pulumi_profile_selector::{Profile, WriteOptions, add_profile, get_pulumi_profiles_path, read_pulumi_profiles};
+//!
+//! let path = get_pulumi_profiles_path()?;
+//! let profile = Profile::new("dev".to_string(), "s3://state-dev".to_string());
+//! add_profile(&path, profile, false, &WriteOptions::default())?;
+//! for profile in read_pulumi_profiles(&path)? {
+//!     println!("{} -> {}", profile.name, profile.backend);
+//! }
+//! # Ok::<(), pulumi_profile_selector::ConfigError>(())
+//! ```
+//!
+//! Failures are reported as [`ConfigError`], so callers can tell, say, a
+//! missing profile from a failed write.
+
+pub mod config;
+pub mod history;
+#[cfg(feature = "reachability")]
+pub mod reachability;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "keyring")]
+pub mod secrets;
+pub mod shell;
+pub mod templates;
+
+pub use config::{
+    ConfigError, Profile, WriteOptions, add_profile, delete_profile, edit_profile, get_credentials_path,
+    get_current_profile_path, get_pulumi_profiles_path, read_pulumi_profiles, save_pulumi_profiles,
+};
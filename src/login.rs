@@ -0,0 +1,88 @@
+use anyhow::Result;
+use pulumi_profile_selector::config::Profile;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where to install the Pulumi CLI from, for the error when it's missing.
+const INSTALL_URL: &str = "https://www.pulumi.com/docs/install/";
+
+/// Runs `pulumi login <backend>` for `profile` with its variables (backend,
+/// access token, extras) set, so the CLI's own state matches the activation.
+/// Under `--current` the CLI's stdout goes to stderr, keeping stdout for the
+/// commands a wrapper evals.
+pub fn pulumi_login(profile: &Profile, current_shell_mode: bool) -> Result<()> {
+    let pulumi = find_on_path("pulumi", env::var_os("PATH").as_deref()).ok_or_else(|| {
+        anyhow::anyhow!("--login needs the pulumi CLI on PATH; install it from {INSTALL_URL}")
+    })?;
+
+    let mut command = login_command(&pulumi, profile);
+    if current_shell_mode {
+        command.stdout(Stdio::from(io::stderr()));
+    }
+    log::debug!("Running {} login {}", pulumi.display(), profile.backend);
+    let status = command
+        .status()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {e}", pulumi.display()))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("pulumi login failed for profile '{}' ({status})", profile.name));
+    }
+
+    Ok(())
+}
+
+fn login_command(pulumi: &Path, profile: &Profile) -> Command {
+    let mut command = Command::new(pulumi);
+    command.arg("login").arg(&profile.backend).envs(profile.env_vars());
+    command
+}
+
+/// The first `name` executable in the `PATH`-style list `path`, trying the
+/// `.exe` suffix too so the same lookup works on Windows.
+fn find_on_path(name: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    let candidates = [name.to_string(), format!("{name}.exe")];
+    env::split_paths(path?)
+        .flat_map(|dir| candidates.iter().map(move |candidate| dir.join(candidate)))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::fs;
+
+    #[test]
+    fn test_find_on_path_takes_the_first_match() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        fs::write(second.path().join("pulumi"), "").unwrap();
+        let path = env::join_paths([first.path(), second.path()]).unwrap();
+
+        assert_eq!(find_on_path("pulumi", Some(&path)), Some(second.path().join("pulumi")));
+        fs::write(first.path().join("pulumi.exe"), "").unwrap();
+        assert_eq!(find_on_path("pulumi", Some(&path)), Some(first.path().join("pulumi.exe")));
+
+        assert_eq!(find_on_path("pulumi", Some(&OsString::new())), None);
+        assert_eq!(find_on_path("pulumi", None), None);
+        fs::create_dir(first.path().join("tofu")).unwrap();
+        assert_eq!(find_on_path("tofu", Some(&path)), None, "directories aren't executables");
+    }
+
+    #[test]
+    fn test_login_command_passes_backend_and_token() {
+        let mut profile = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        profile.access_token = Some("pul-123".to_string());
+
+        let command = login_command(Path::new("/usr/bin/pulumi"), &profile);
+        let args: Vec<&OsStr> = command.get_args().collect();
+        assert_eq!(args, ["login", "https://api.pulumi.com"]);
+        let token = command
+            .get_envs()
+            .find(|(name, _)| *name == "PULUMI_ACCESS_TOKEN")
+            .and_then(|(_, value)| value);
+        assert_eq!(token, Some(OsStr::new("pul-123")));
+    }
+}
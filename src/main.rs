@@ -1,23 +1,1263 @@
-mod config;
+mod completions;
+mod doctor;
+mod init;
+mod login;
+mod settings;
 mod ui;
 
-use anyhow::Result;
-use clap::{Arg, Command, ArgAction};
-use config::{read_pulumi_profiles, add_profile, edit_profile, delete_profile};
-use ui::{ProfileSelector, prompt_for_profile_details, prompt_for_backend_url};
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+use completions::generate_completions;
+use doctor::{Severity, diagnose};
+use init::{INIT_SHELLS, init_script};
+use login::pulumi_login;
+use pulumi_profile_selector::config::{
+    ConfigError, DEFAULT_BACKUPS, MANAGED_ENV_VARS, MergeStrategy, PROFILES_JSON_ENV, Profile,
+    ProfilesSource, RepairOutcome, Resolution, SORT_KEYS, SortKey, WriteOptions, add_profile,
+    check_active_deletion, check_extra_env_var, check_not_overlay_only, clear_current_profile,
+    clone_profile, dedupe_profiles, default_profile, delete_profile, delete_profiles, edit_profile,
+    env_backend_matches, expand_backend, export_profiles, extra_env_var_names, file_backend_path,
+    filter_by_glob, filter_by_query, filter_by_tags, find_by_backend, find_duplicate_names,
+    get_credentials_path, get_current_profile_path, get_local_profiles_path,
+    get_pulumi_profiles_path, get_selector_cursor_path, import_from_credentials, import_profiles,
+    load_pulumi_profiles, mark_profile_used, most_recent_profile, next_profile, normalize_backend,
+    ordered_profiles, pin_profile, plan_backend_replacements, previous_profile, profile_at_index,
+    profiles_json_schema, read_current_profile, read_current_profile_entry, read_selector_cursor,
+    rename_profile, repair_profiles, replace_in_backends, resolve_file_backend,
+    resolve_group_backends, resolve_profile, set_default_profile, set_token_in_keyring,
+    sort_profiles, unpin_profile, write_atomic, write_current_profile, write_selector_cursor,
+};
+use pulumi_profile_selector::history::{
+    append_audit_entry, format_iso8601, format_timestamp, get_history_path, read_history, record_activation,
+};
+#[cfg(feature = "reachability")]
+use pulumi_profile_selector::reachability::{CheckOutcome, check_backend};
+#[cfg(feature = "remote")]
+use pulumi_profile_selector::remote::fetch_profiles;
+#[cfg(feature = "keyring")]
+use pulumi_profile_selector::secrets;
+use pulumi_profile_selector::shell::{
+    ShellKind, check_shell_values, detect_shell, format_env_header, format_env_lines,
+};
+use pulumi_profile_selector::templates::{apply_template, get_templates_path, read_templates};
+use settings::{LIST_FORMATS, SHELL_NAMES, Settings, get_settings_path};
+use ui::{
+    COLOR_CHOICES, ColorChoice, DEFAULT_PAGE_SIZE, ProfileSelector, Selection, bold,
+    confirm_activation, confirm_deletion, confirm_edit_backend, dim, format_profile_line,
+    format_profile_lines, format_profile_table, is_prompt_cancelled, prompt_for_access_token,
+    prompt_for_backend_url, prompt_for_backend_url_with_default, prompt_for_profile_details,
+    prompt_for_profiles_to_delete, prompt_for_profiles_to_import, resolve_color, run_watch_loop,
+};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Exit codes, documented in the --help epilog
+const EXIT_NO_PROFILES: i32 = 1;
+const EXIT_PROFILE_NOT_FOUND: i32 = 2;
+const EXIT_CANCELLED: i32 = 3;
+const EXIT_UNKNOWN_SHELL: i32 = 4;
+const EXIT_DOCTOR_FAILED: i32 = 5;
+
+// Larger page sizes than this just scroll off any real terminal
+const MAX_PAGE_SIZE: usize = 100;
+
+// How long --check waits for the backend to answer
+const DEFAULT_CHECK_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_PROFILES_TIMEOUT_SECS: u64 = 10;
+
+const DEFAULT_HISTORY_COUNT: &str = "10";
 
 fn main() -> Result<()> {
-    let matches = Command::new("pulumi-profile-selector")
+    let matches = build_cli().get_matches();
+
+    // Quiet by default; RUST_LOG still refines the filter when set
+    env_logger::Builder::new()
+        .filter_level(if matches.get_flag("verbose") {
+            log::LevelFilter::Debug
+        } else {
+            log::LevelFilter::Off
+        })
+        .parse_default_env()
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    if let Some(shell) = matches.get_one::<String>("generate-completions") {
+        print!("{}", generate_completions(shell, &mut build_cli()));
+        return Ok(());
+    }
+
+    if let Some(shell) = matches.get_one::<String>("init") {
+        print!("{}", init_script(shell, build_cli().get_name()));
+        return Ok(());
+    }
+
+    if matches.get_flag("json-schema") {
+        println!("{}", profiles_json_schema());
+        return Ok(());
+    }
+
+    // Flags given on the command line (or via their env vars) beat config.toml
+    let flags = Settings {
+        page_size: matches.get_one::<usize>("page-size").copied(),
+        format: matches.get_one::<String>("format").cloned(),
+        color: matches.get_flag("no-color").then_some(false),
+        shell: matches.get_one::<String>("shell").cloned(),
+        sort: matches.get_one::<String>("sort").cloned(),
+        audit_log: matches.get_one::<PathBuf>("audit-log").cloned(),
+    };
+    let settings_path = get_settings_path()?;
+    let settings = flags.or(Settings::load(&settings_path)?);
+
+    // Inline and remote profiles replace the file, so there is no profiles path to resolve
+    let profiles_source = match matches.get_one::<String>("profiles-url") {
+        // Fetched once up front; every read below sees the same content
+        Some(url) => {
+            let secs = matches.get_one::<u64>("profiles-timeout").copied();
+            let timeout = Duration::from_secs(secs.unwrap_or(DEFAULT_PROFILES_TIMEOUT_SECS));
+            ProfilesSource::Remote { url: url.clone(), content: fetch_remote_profiles(url, timeout)? }
+        }
+        None => ProfilesSource::resolve(|| match matches.get_one::<PathBuf>("config") {
+            Some(path) => Ok(path.clone()),
+            None => get_pulumi_profiles_path(),
+        })?,
+    };
+    log::debug!("Profiles source: {profiles_source}");
+
+    // Used by the completion scripts; stays silent rather than creating or reporting anything
+    if matches.get_flag("complete-profiles") {
+        if let Ok(profiles) = profiles_source.load() {
+            for profile in &profiles {
+                println!("{}", profile.name);
+            }
+        }
+        return Ok(());
+    }
+    let current_profile_path = get_current_profile_path()?;
+    log::debug!("Current profile path: {}", current_profile_path.display());
+    let current_shell_mode = matches.get_flag("current");
+    let force = matches.get_flag("force");
+    // --color beats --no-color and config.toml's `color = false`
+    let color_choice = match matches.get_one::<String>("color") {
+        Some(name) => ColorChoice::from_name(name).unwrap_or_default(),
+        None if settings.color == Some(false) => ColorChoice::Never,
+        None => ColorChoice::Auto,
+    };
+    let quiet = matches.get_flag("quiet");
+    let shell_override = settings.shell.as_deref();
+    let sort = settings.sort.as_deref().and_then(SortKey::from_name).unwrap_or_default();
+    let audit_log = settings.audit_log.as_deref();
+    // Only --current output needs a shell; an unrecognized one stops here, before anything changes
+    let shell = current_shell_mode.then(|| resolve_shell(shell_override, matches.get_flag("assume-posix")));
+    let dry_run = matches.get_flag("dry-run");
+    // Changes are still validated against the profiles, just never written
+    let write_options = WriteOptions {
+        backups: matches.get_one::<usize>("backups").copied().unwrap_or(DEFAULT_BACKUPS),
+        dry_run,
+        case_sensitive: matches.get_flag("case-sensitive"),
+    };
+    let no_create = matches.get_flag("no-create") || dry_run;
+
+    if matches.get_flag("which") {
+        let describe = |path: &Path| {
+            let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+            let state = if path.exists() { "exists" } else { "missing" };
+            format!("{} ({})", path.display(), state)
+        };
+
+        match &profiles_source {
+            ProfilesSource::File(path) => {
+                println!("profiles:        {}", describe(path));
+                println!("local profiles:  {}", describe(&get_local_profiles_path(path)));
+            }
+            ProfilesSource::Inline(_) => println!("profiles:        {} (inline)", PROFILES_JSON_ENV),
+            ProfilesSource::Remote { url, .. } => println!("profiles:        {url} (remote)"),
+        }
+        println!("current_profile: {}", describe(&current_profile_path));
+        println!("settings:        {}", describe(&settings_path));
+        println!("history:         {}", describe(&get_history_path()?));
+        println!("templates:       {}", describe(&get_templates_path()?));
+        return Ok(());
+    }
+
+    if matches.get_flag("doctor") {
+        let findings = diagnose(&profiles_source, &current_profile_path, detect_shell(shell_override));
+        for finding in &findings {
+            println!("{finding}");
+        }
+        if findings.iter().any(|f| f.severity == Severity::Error) {
+            std::process::exit(EXIT_DOCTOR_FAILED);
+        }
+        return Ok(());
+    }
+
+    // Reads always merge the overlay; --local makes the commands below write to it instead
+    let local_source = matches
+        .get_flag("local")
+        .then(|| profiles_source.path().map(get_local_profiles_path))
+        .flatten();
+    let profiles_source = local_source.map_or(profiles_source, ProfilesSource::File);
+
+    // Handle profile management commands first
+    if matches.get_flag("add") {
+        let name = matches.get_one::<String>("name").cloned();
+        let backend = matches.get_one::<String>("backend").cloned();
+        let profile = match (name, backend) {
+            // Scripts supply everything up front and can't answer prompts
+            (Some(name), Some(backend)) if !io::stdin().is_terminal() => Profile::new(name, backend),
+            (name, backend) => {
+                let existing = load_pulumi_profiles(profiles_source.writable_path()?)?;
+                prompt_for_profile_details(name, backend, &existing, write_options.case_sensitive)?
+            }
+        };
+        let (name, backend) = (profile.name.clone(), normalize_backend(&profile.backend));
+        add_profile(profiles_source.writable_path()?, profile, force, &write_options)?;
+        if dry_run {
+            println!("Would add profile '{}' -> {}", name, backend);
+        } else {
+            info(quiet, format_args!("Profile '{}' added successfully", name));
+        }
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("edit") {
+        check_not_overlay_only(profiles_source.writable_path()?, profile_name)?;
+        let new_backend = match matches.get_one::<String>("backend") {
+            Some(backend) => backend.clone(),
+            None => {
+                // Look the profile up first so a typo fails before anything is typed.
+                // A group member's stored suffix is what gets edited
+                let profiles = load_pulumi_profiles(profiles_source.writable_path()?)?;
+                let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) else {
+                    return Err(ConfigError::ProfileNotFound(profile_name.clone()).into());
+                };
+                prompt_for_backend_url_with_default(&profile.backend)?
+            }
+        };
+        edit_profile(profiles_source.writable_path()?, profile_name, new_backend.clone(), force, &write_options)?;
+        if dry_run {
+            println!(
+                "Would change the backend of profile '{}' to {}",
+                profile_name,
+                normalize_backend(&new_backend)
+            );
+        } else {
+            info(quiet, format_args!("Profile '{}' updated successfully", profile_name));
+        }
+        return Ok(());
+    }
+
+    if let Some(export_path) = matches.get_one::<PathBuf>("export") {
+        if dry_run {
+            let count = profiles_source.load()?.len();
+            println!("Would export {} profile(s) to {}", count, export_path.display());
+        } else {
+            let count = export_profiles(&profiles_source, export_path)?;
+            info(quiet, format_args!("Exported {} profile(s) to {}", count, export_path.display()));
+        }
+        return Ok(());
+    }
+
+    if let Some(import_path) = matches.get_one::<PathBuf>("import") {
+        let strategy = match matches.get_one::<String>("on-conflict").map(String::as_str) {
+            Some("overwrite") => MergeStrategy::Overwrite,
+            Some("rename") => MergeStrategy::Rename,
+            _ => MergeStrategy::Skip,
+        };
+        let summary = import_profiles(profiles_source.writable_path()?, import_path, strategy, &write_options)?;
+
+        info(quiet, format_args!("Imported {} new profile(s)", summary.added.len()));
+        for name in &summary.skipped {
+            info(quiet, format_args!("  Skipped '{}' (already exists)", name));
+        }
+        for name in &summary.overwritten {
+            info(quiet, format_args!("  Overwrote '{}'", name));
+        }
+        for (old_name, new_name) in &summary.renamed {
+            info(quiet, format_args!("  Imported '{}' as '{}' (name already exists)", old_name, new_name));
+        }
+        return Ok(());
+    }
+
+    if let Some(template_name) = matches.get_one::<String>("apply-template") {
+        let templates_path = get_templates_path()?;
+        let templates = read_templates(&templates_path)?;
+        let Some(template) = templates.iter().find(|t| &t.name == template_name) else {
+            return Err(anyhow::anyhow!(
+                "Template '{}' is not defined in {}",
+                template_name,
+                templates_path.display()
+            ));
+        };
+        let vars: BTreeMap<String, String> =
+            matches.get_many::<(String, String)>("var").unwrap_or_default().cloned().collect();
+        let summary = apply_template(profiles_source.writable_path()?, template, &vars, force, &write_options)?;
+
+        if dry_run {
+            for name in &summary.created {
+                println!("Would add profile '{}'", name);
+            }
+        } else {
+            info(quiet, format_args!("Created {} profile(s) from template '{}'", summary.created.len(), template_name));
+        }
+        for name in &summary.skipped {
+            info(quiet, format_args!("  Skipped '{}' (already exists)", name));
+        }
+        return Ok(());
+    }
+
+    if matches.get_flag("import-credentials") {
+        let existing = read_profiles(&profiles_source, no_create)?;
+        let candidates = import_from_credentials(&get_credentials_path()?, &existing)?;
+        if candidates.is_empty() {
+            info(quiet, "No new backends found in Pulumi credentials");
+            return Ok(());
+        }
+
+        let selected = prompt_for_profiles_to_import(&candidates)?;
+        let count = selected.len();
+        for profile in selected {
+            // Backends come straight from `pulumi login`, so trust them as-is
+            add_profile(profiles_source.writable_path()?, profile, true, &write_options)?;
+        }
+        info(
+            quiet,
+            format_args!("Imported {} of {} new backend(s) from Pulumi credentials", count, candidates.len()),
+        );
+        return Ok(());
+    }
+
+    if let Some(mut names) = matches.get_many::<String>("clone") {
+        let (source, new_name) = (names.next().unwrap(), names.next().unwrap());
+        clone_profile(profiles_source.writable_path()?, source, new_name, &write_options)?;
+        info(quiet, format_args!("Profile '{}' cloned to '{}'", source, new_name));
+
+        if !matches.get_flag("yes") && confirm_edit_backend(new_name)? {
+            let new_backend = prompt_for_backend_url()?;
+            edit_profile(profiles_source.writable_path()?, new_name, new_backend, force, &write_options)?;
+            info(quiet, format_args!("Profile '{}' updated successfully", new_name));
+        }
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("pin") {
+        pin_profile(profiles_source.writable_path()?, profile_name, &write_options)?;
+        info(quiet, format_args!("Profile '{}' pinned", profile_name));
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("set-default") {
+        set_default_profile(profiles_source.writable_path()?, profile_name, &write_options)?;
+        info(quiet, format_args!("Profile '{}' is now the default", profile_name));
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("set-token") {
+        ensure_keyring_support("--set-token")?;
+        let profiles = load_pulumi_profiles(profiles_source.writable_path()?)?;
+        let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) else {
+            exit_profile_not_found(profile_name, &profiles)
+        };
+        if !profile.is_cloud_backend() {
+            eprintln!(
+                "Warning: profile '{}' doesn't use a Pulumi Cloud backend, so its token is never exported",
+                profile.name
+            );
+        }
+
+        // Scripts pipe the token in rather than answering a prompt
+        let token = if io::stdin().is_terminal() {
+            prompt_for_access_token(profile_name)?
+        } else {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            line.trim().to_string()
+        };
+        if token.is_empty() {
+            return Err(anyhow::anyhow!("No access token given for profile '{}'", profile_name));
+        }
+        if dry_run {
+            println!("Would store the access token of '{}' in the OS keyring", profile_name);
+            return Ok(());
+        }
+
+        // The keyring goes first so a failure there leaves any plaintext token in place
+        store_keyring_token(profile_name, &token)?;
+        set_token_in_keyring(profiles_source.writable_path()?, profile_name, true, &write_options)?;
+        info(quiet, format_args!("Access token of '{}' stored in the OS keyring", profile_name));
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("clear-token") {
+        ensure_keyring_support("--clear-token")?;
+        if dry_run {
+            set_token_in_keyring(profiles_source.writable_path()?, profile_name, false, &write_options)?;
+            println!("Would remove the access token of '{}' from the OS keyring", profile_name);
+            return Ok(());
+        }
+        delete_keyring_token(profile_name)?;
+        set_token_in_keyring(profiles_source.writable_path()?, profile_name, false, &write_options)?;
+        info(quiet, format_args!("Access token of '{}' removed from the OS keyring", profile_name));
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("unpin") {
+        unpin_profile(profiles_source.writable_path()?, profile_name, &write_options)?;
+        info(quiet, format_args!("Profile '{}' unpinned", profile_name));
+        return Ok(());
+    }
+
+    if let Some(mut names) = matches.get_many::<String>("rename") {
+        let (old_name, new_name) = (names.next().unwrap(), names.next().unwrap());
+        check_not_overlay_only(profiles_source.writable_path()?, old_name)?;
+        match rename_profile(profiles_source.writable_path()?, old_name, new_name, &write_options) {
+            Err(ConfigError::ProfileNotFound(_)) => {
+                exit_profile_not_found(old_name, &profiles_source.load().unwrap_or_default())
+            }
+            result => result?,
+        }
+        if dry_run {
+            println!("Would rename profile '{}' to '{}'", old_name, new_name);
+            return Ok(());
+        }
+
+        // Keep the active selection pointing at the renamed profile
+        if let Some(current) = read_current_profile_entry(&current_profile_path)?
+            && &current.name == old_name
+        {
+            write_current_profile(&current_profile_path, new_name, current.backend.as_deref())?;
+        }
+
+        info(quiet, format_args!("Profile '{}' renamed to '{}'", old_name, new_name));
+        return Ok(());
+    }
+
+    if matches.contains_id("delete") && matches.get_one::<String>("delete").is_none() {
+        let profiles = read_profiles(&profiles_source, no_create)?;
+        if profiles.is_empty() {
+            println!("No profiles found.");
+            return Ok(());
+        }
+
+        let selected = prompt_for_profiles_to_delete(&profiles)?;
+        if selected.is_empty() {
+            info(quiet, "No profiles selected; nothing deleted");
+            return Ok(());
+        }
+        let names: Vec<&str> = selected.iter().map(String::as_str).collect();
+        let was_active = check_active_deletion(&current_profile_path, &names, force)?;
+        let profiles_path = profiles_source.writable_path()?;
+        for name in &names {
+            check_not_overlay_only(profiles_path, name)?;
+        }
+
+        // The names were picked from the profiles just read, so they all exist
+        if dry_run {
+            println!("Would delete {} profile(s): {}", names.len(), names.join(", "));
+            return Ok(());
+        }
+        let count = delete_profiles(profiles_path, &names, &write_options)?;
+        if was_active {
+            clear_current_profile(&current_profile_path)?;
+        }
+        info(quiet, format_args!("Deleted {} profile(s)", count));
+        return Ok(());
+    }
+
+    if let Some(profile_name) = matches.get_one::<String>("delete") {
+        let profiles = read_profiles(&profiles_source, no_create)?;
+        let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) else {
+            exit_profile_not_found(profile_name, &profiles)
+        };
+        // Found in the merged view, but the deletion only sees the file it changes
+        check_not_overlay_only(profiles_source.writable_path()?, profile_name)?;
+
+        let was_active = check_active_deletion(&current_profile_path, &[profile_name], force)?;
+
+        if dry_run {
+            delete_profile(profiles_source.writable_path()?, profile_name, &write_options)?;
+            println!("Would delete profile '{}' -> {}", profile_name, profile.backend);
+            return Ok(());
+        }
+
+        if !matches.get_flag("yes") && !confirm_deletion(profile)? {
+            info(quiet, "Aborted");
+            return Ok(());
+        }
+
+        delete_profile(profiles_source.writable_path()?, profile_name, &write_options)?;
+        if was_active {
+            clear_current_profile(&current_profile_path)?;
+        }
+        info(quiet, format_args!("Profile '{}' deleted successfully", profile_name));
+        return Ok(());
+    }
+
+    if matches.get_flag("dedupe") {
+        let removed = dedupe_profiles(profiles_source.writable_path()?, &write_options)?;
+        if dry_run {
+            println!("Would remove {removed} duplicate profile(s)");
+        } else {
+            info(quiet, format_args!("Removed {removed} duplicate profile(s)"));
+        }
+        return Ok(());
+    }
+
+    if let Some(values) = matches.get_many::<String>("replace-in-backends") {
+        let values: Vec<&String> = values.collect();
+        let (from, to) = (values[0], values[1]);
+        let count = replace_in_backends(profiles_source.writable_path()?, from, to, force, &write_options)?;
+        if dry_run {
+            for change in plan_backend_replacements(&load_pulumi_profiles(profiles_source.writable_path()?)?, from, to) {
+                println!("Would change '{}': {} -> {}", change.name, change.before, change.after);
+            }
+            println!("Would update {count} profile backend(s)");
+        } else {
+            info(quiet, format_args!("Updated {count} profile backend(s)"));
+        }
+        return Ok(());
+    }
+
+    if matches.get_flag("repair") {
+        let profiles_path = profiles_source.writable_path()?;
+        match repair_profiles(profiles_path)? {
+            RepairOutcome::Healthy => info(quiet, "Profiles file is valid; nothing to repair"),
+            RepairOutcome::Wrapped { backup } => info(
+                quiet,
+                format_args!(
+                    "Wrapped the single profile in {} in a list (original saved to {})",
+                    profiles_path.display(),
+                    backup.display()
+                ),
+            ),
+            RepairOutcome::Reset { backup } => info(
+                quiet,
+                format_args!(
+                    "Reset {} to an empty list (original saved to {})",
+                    profiles_path.display(),
+                    backup.display()
+                ),
+            ),
+        }
+        return Ok(());
+    }
+
+    if matches.get_flag("status") {
+        let color = resolve_color(color_choice, io::stdout().is_terminal());
+        match read_current_profile_entry(&current_profile_path)? {
+            Some(current) => {
+                let profiles = read_profiles(&profiles_source, no_create)?;
+                println!("Active profile: {}", bold(&current.name, color));
+                match profiles.iter().find(|p| p.name == current.name) {
+                    Some(profile) => {
+                        println!("Backend: {}", dim(&profile.backend, color));
+                        if let Some(notes) = &profile.notes {
+                            println!("Notes: {notes}");
+                        }
+                        if let Some(activated) = &current.backend
+                            && expand_backend(&profile.backend)
+                                .is_ok_and(|backend| normalize_backend(&backend) != normalize_backend(activated))
+                        {
+                            eprintln!(
+                                "Warning: profile '{}' was activated with backend {}; activate it again to use the edited one",
+                                profile.name, activated
+                            );
+                        }
+                        if !env_backend_matches(profile) {
+                            eprintln!(
+                                "Warning: PULUMI_BACKEND_URL in this shell is {}, which Pulumi uses instead; re-run with --current to update it",
+                                env::var("PULUMI_BACKEND_URL").unwrap_or_default()
+                            );
+                        }
+                    }
+                    None => {
+                        // The recorded backend is still what the activation exported
+                        if let Some(backend) = &current.backend {
+                            println!("Backend: {}", dim(backend, color));
+                        }
+                        eprintln!(
+                            "Warning: active profile '{}' is not defined in {}",
+                            current.name,
+                            profiles_source
+                        );
+                    }
+                }
+            }
+            None => println!("No active profile"),
+        }
+        return Ok(());
+    }
+
+    if let Some(&count) = matches.get_one::<usize>("history") {
+        let history = read_history(&get_history_path()?, count)?;
+        if history.is_empty() {
+            println!("No activations recorded yet");
+        }
+        for entry in &history {
+            println!("{}  {}", format_timestamp(entry.activated_at), entry.name);
+        }
+        return Ok(());
+    }
+
+    if matches.get_flag("count") {
+        println!("{}", read_profiles(&profiles_source, no_create)?.len());
+        return Ok(());
+    }
+
+    if matches.get_flag("list") {
+        let profiles = read_profiles(&profiles_source, no_create)?;
+        let color = resolve_color(color_choice, io::stdout().is_terminal());
+        // Numbered after sorting, so --activate-index with the same --sort agrees
+        let numbered = ordered_profiles(&profiles, sort);
+        match settings.format.as_deref() {
+            // Only the JSON document goes to stdout so it can be piped into jq
+            Some("json") => {
+                let sorted: Vec<&Profile> = numbered.iter().map(|(_, profile)| *profile).collect();
+                println!("{}", serde_json::to_string_pretty(&sorted)?)
+            }
+            Some("jsonl") => print!("{}", format_profile_lines(&numbered)?),
+            _ if profiles.is_empty() => println!("No profiles found."),
+            Some("plain") => {
+                println!("Available profiles:");
+                for (index, profile) in numbered {
+                    println!("  {index}. {}", format_profile_line(profile, color));
+                }
+            }
+            _ => print!("{}", format_profile_table(&numbered, color)),
+        }
+        return Ok(());
+    }
+
+    // Handle deactivation
+    if matches.get_flag("deactivate") {
+        if let Some(shell) = shell {
+            // Output shell-specific unset command
+            print_shell_command(&profiles_source, &current_profile_path, None, shell)?;
+            let profiles = profiles_source.load().unwrap_or_default();
+            let name = active_profile_name(&profiles, &current_profile_path, true).ok().flatten();
+            write_audit_entry(audit_log, name.as_deref(), None);
+        } else {
+            if current_profile_path.exists() {
+                // An unreadable file is still cleared, just logged without a name
+                let name = read_current_profile(&current_profile_path).ok().flatten();
+                clear_current_profile(&current_profile_path)?;
+                write_audit_entry(audit_log, name.as_deref(), None);
+                info(quiet, "Pulumi profile deactivated");
+            } else {
+                info(quiet, "No active Pulumi profile to deactivate");
+            }
+        }
+        return Ok(());
+    }
+
+    // Only the file external tools read is written: no exports, history or selector
+    if let Some(profile_name) = matches.get_one::<String>("set-current") {
+        let profiles = read_profiles(&profiles_source, no_create)?;
+        let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) else {
+            exit_profile_not_found(profile_name, &profiles);
+        };
+        let backend = expand_backend(&profile.backend)?;
+        if dry_run {
+            println!("Would set the current profile to '{profile_name}'");
+        } else {
+            write_current_profile(&current_profile_path, profile_name, Some(&backend))?;
+            info(quiet, format_args!("Current profile set to '{profile_name}'"));
+        }
+        return Ok(());
+    }
+
+    // Handle new profile (doesn't require reading existing profiles)
+    if let Some(profile_name) = matches.get_one::<String>("new") {
+        if let Some(shell) = shell {
+            // Output shell-specific export command
+            print_shell_command(&profiles_source, &current_profile_path, Some(profile_name), shell)?;
+        } else {
+            write_current_profile(&current_profile_path, profile_name, None)?;
+            info(quiet, format_args!("Pulumi profile activated: {profile_name}"));
+        }
+        return Ok(());
+    }
+
+    let profiles = read_profiles(&profiles_source, no_create)?;
+
+    if profiles.is_empty() {
+        eprintln!("No Pulumi profiles found");
+        eprintln!("Use --add to create your first profile");
+        std::process::exit(EXIT_NO_PROFILES);
+    }
+
+    // Plain KEY=VALUE output for env files, independent of the shell
+    if let Some(profile_name) = matches.get_one::<String>("print-env") {
+        match profiles.iter().find(|p| &p.name == profile_name) {
+            Some(profile) => {
+                let profile = with_keyring_token(Profile {
+                    backend: expand_backend(&profile.backend)?,
+                    ..profile.clone()
+                })?;
+                print!("{}", format_env_lines(&profile.env_vars()));
+            }
+            None => exit_profile_not_found(profile_name, &profiles),
+        }
+        return Ok(());
+    }
+
+    // The same lines as --print-env, written to a file such as docker-compose's .env
+    if let Some(profile_name) = matches.get_one::<String>("export-env-file") {
+        let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) else {
+            exit_profile_not_found(profile_name, &profiles)
+        };
+        let profile = with_keyring_token(Profile {
+            backend: expand_backend(&profile.backend)?,
+            ..profile.clone()
+        })?;
+
+        let mut content = String::new();
+        if matches.get_flag("header") {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+            content.push_str(&format_env_header(&profile.name, &format_iso8601(now)));
+        }
+        content.push_str(&format_env_lines(&profile.env_vars()));
+
+        match matches.get_one::<PathBuf>("out") {
+            Some(path) if dry_run => {
+                println!("Would write the environment of '{}' to {}", profile.name, path.display())
+            }
+            Some(path) => {
+                write_atomic(path, content.as_bytes())
+                    .with_context(|| format!("Failed to write env file {}", path.display()))?;
+                info(quiet, format_args!("Wrote the environment of '{}' to {}", profile.name, path.display()));
+            }
+            None => print!("{content}"),
+        }
+        return Ok(());
+    }
+
+    // Offer the selector again after every activation, re-reading the profiles
+    // each time so edits made in the meantime show up
+    if matches.get_flag("watch") {
+        let tags: Vec<String> = matches.get_many::<String>("tag").unwrap_or_default().cloned().collect();
+        let query = matches.get_one::<String>("filter");
+        run_watch_loop(
+            || {
+                let mut candidates = filter_by_tags(read_profiles(&profiles_source, no_create)?, &tags);
+                if let Some(query) = query {
+                    candidates = filter_by_query(candidates, query);
+                }
+                let mut selector =
+                    build_selector(candidates, &profiles_source, &current_profile_path, &settings, color_choice, sort)?;
+                selector.run()
+            },
+            |selection| {
+                let profiles = profiles_source.load()?;
+                let profile = match selection {
+                    Selection::Profile(name) => {
+                        if !dry_run {
+                            remember_selection(&profiles_source, &name);
+                        }
+                        profiles
+                            .into_iter()
+                            .find(|p| p.name == name)
+                            .ok_or_else(|| anyhow::anyhow!("Profile '{}' was removed meanwhile", name))?
+                    }
+                    Selection::AddNew => {
+                        let profile = prompt_for_profile_details(None, None, &profiles, write_options.case_sensitive)?;
+                        add_profile(profiles_source.writable_path()?, profile.clone(), force, &write_options)?;
+                        profile
+                    }
+                };
+                if matches.get_flag("confirm") && !confirm_activation(&profile)? {
+                    return Ok(());
+                }
+                activate_profile(&matches, profile, &profiles_source, &current_profile_path, None, audit_log)
+            },
+        )?;
+        return Ok(());
+    }
+
+    // Handle direct profile activation
+    let selected_profile = if let Some(profile_name) = matches.get_one::<String>("activate") {
+        // Validate that the profile exists and get its backend URL
+        match resolve_profile(&profiles, profile_name) {
+            Resolution::Exact(profile) => Some(profile.clone()),
+            Resolution::UniquePrefix(profile) => {
+                eprintln!("Using profile '{}' for '{}'", profile.name, profile_name);
+                Some(profile.clone())
+            }
+            Resolution::Ambiguous(candidates) => {
+                eprintln!("Profile '{}' is ambiguous; it matches:", profile_name);
+                for profile in candidates {
+                    eprintln!("  {}", profile.name);
+                }
+                std::process::exit(EXIT_PROFILE_NOT_FOUND);
+            }
+            Resolution::NotFound => exit_profile_not_found(profile_name, &profiles),
+        }
+    } else if let Some(&index) = matches.get_one::<usize>("activate-index") {
+        match profile_at_index(&profiles, sort, index) {
+            Some(profile) => Some(profile.clone()),
+            None => {
+                eprintln!(
+                    "Profile index {} is out of range; valid indices are 1-{} (see --list)",
+                    index,
+                    profiles.len()
+                );
+                std::process::exit(EXIT_PROFILE_NOT_FOUND);
+            }
+        }
+    } else if matches.get_flag("recent") {
+        match most_recent_profile(&profiles) {
+            Some(profile) => Some(profile.clone()),
+            None => {
+                eprintln!("No profile has been activated yet; nothing to re-activate");
+                std::process::exit(EXIT_PROFILE_NOT_FOUND);
+            }
+        }
+    } else if matches.get_flag("rotate") || matches.get_flag("rotate-back") {
+        let mut sorted = profiles.clone();
+        sort_profiles(&mut sorted, sort);
+        let active = active_profile_name(&profiles, &current_profile_path, current_shell_mode)?;
+        if matches.get_flag("rotate") {
+            Some(next_profile(&sorted, active.as_deref()).clone())
+        } else {
+            Some(previous_profile(&sorted, active.as_deref()).clone())
+        }
+    } else if current_shell_mode
+        && !matches.contains_id("find-backend")
+        && !matches.contains_id("tag")
+        && !matches.contains_id("filter")
+        && !matches.contains_id("activate-glob")
+        && let Some(profile) = default_profile(&profiles)
+    {
+        // Scripts eval --current without a terminal to pick in, so the default stands in
+        Some(profile.clone())
+    } else if let Some(needle) = matches.get_one::<String>("find-backend")
+        && let [profile] = find_by_backend(&profiles, needle).as_slice()
+    {
+        // A single match only needs a yes or no
+        if matches.get_flag("yes") || confirm_activation(profile)? {
+            Some((*profile).clone())
+        } else {
+            None
+        }
+    } else {
+        let candidates = if let Some(needle) = matches.get_one::<String>("find-backend") {
+            let found: Vec<Profile> = find_by_backend(&profiles, needle).into_iter().cloned().collect();
+            if found.is_empty() {
+                eprintln!("No profile has a backend containing '{}'", needle);
+                std::process::exit(EXIT_PROFILE_NOT_FOUND);
+            }
+            found
+        } else {
+            // Run interactive selector over the profiles matching every --tag and the --filter query
+            let tags: Vec<String> = matches.get_many::<String>("tag").unwrap_or_default().cloned().collect();
+            let tagged = filter_by_tags(profiles.clone(), &tags);
+            if tagged.is_empty() {
+                eprintln!("No profiles tagged with: {}", tags.join(", "));
+                std::process::exit(EXIT_NO_PROFILES);
+            }
+            let filtered = match matches.get_one::<String>("filter") {
+                Some(query) => {
+                    let filtered = filter_by_query(tagged, query);
+                    if filtered.is_empty() {
+                        eprintln!("No profiles match '{}'", query);
+                        std::process::exit(EXIT_NO_PROFILES);
+                    }
+                    filtered
+                }
+                None => tagged,
+            };
+            match matches.get_one::<String>("activate-glob") {
+                Some(pattern) => {
+                    let globbed = filter_by_glob(filtered, pattern);
+                    if globbed.is_empty() {
+                        eprintln!("No profile name matches '{}'", pattern);
+                        std::process::exit(EXIT_PROFILE_NOT_FOUND);
+                    }
+                    globbed
+                }
+                None => filtered,
+            }
+        };
+
+        // A filter that narrows things down to one profile leaves nothing to pick
+        let auto = matches.get_flag("auto") || matches.contains_id("activate-glob");
+        if auto && candidates.len() == 1 {
+            candidates.into_iter().next()
+        } else {
+            let mut selector =
+                build_selector(candidates, &profiles_source, &current_profile_path, &settings, color_choice, sort)?;
+            loop {
+                let selected_name = match selector.run()? {
+                    None => break None,
+                    Some(Selection::Profile(name)) => {
+                        if !dry_run {
+                            remember_selection(&profiles_source, &name);
+                        }
+                        name
+                    }
+                    Some(Selection::AddNew) => match prompt_for_profile_details(
+                        None,
+                        None,
+                        &profiles,
+                        write_options.case_sensitive,
+                    ) {
+                        Ok(profile) => match profiles_source
+                            .writable_path()
+                            .and_then(|path| add_profile(path, profile.clone(), force, &write_options))
+                        {
+                            Ok(()) => break Some(profile),
+                            Err(e) => {
+                                eprintln!("Error: {e}");
+                                continue;
+                            }
+                        },
+                        // Backing out of the add prompt returns to the list
+                        Err(e) if is_prompt_cancelled(&e) => continue,
+                        Err(e) => return Err(e),
+                    },
+                };
+                let profile = profiles.iter().find(|p| p.name == selected_name).cloned();
+
+                // Declining the confirmation goes back to the selector
+                if let Some(profile) = &profile
+                    && matches.get_flag("confirm")
+                    && !confirm_activation(profile)?
+                {
+                    continue;
+                }
+                break profile;
+            }
+        }
+    };
+
+    match selected_profile {
+        Some(profile) => {
+            activate_profile(&matches, profile, &profiles_source, &current_profile_path, shell, audit_log)?
+        }
+        None => {
+            info(quiet, "No profile selected");
+            std::process::exit(EXIT_CANCELLED);
+        }
+    }
+
+    Ok(())
+}
+
+/// The interactive selector over `candidates`, opening on the profile picked
+/// last time.
+fn build_selector(
+    candidates: Vec<Profile>,
+    profiles_source: &ProfilesSource,
+    current_profile_path: &Path,
+    settings: &Settings,
+    color_choice: ColorChoice,
+    sort: SortKey,
+) -> Result<ProfileSelector> {
+    let active = read_current_profile(current_profile_path)?;
+    let page_size = settings.page_size.map_or(DEFAULT_PAGE_SIZE, |n| n.min(MAX_PAGE_SIZE));
+    // The selector draws on stderr, which stays a terminal when stdout is captured by --current
+    Ok(ProfileSelector::new(candidates, active)
+        .with_page_size(page_size)
+        .with_color(resolve_color(color_choice, io::stderr().is_terminal()))
+        .with_add_option(profiles_source.path().is_some())
+        .with_sort(sort)
+        .with_remembered(read_selector_cursor(&get_selector_cursor_path()?)))
+}
+
+fn remember_selection(profiles_source: &ProfilesSource, name: &str) {
+    // Inline and remote profiles leave no state behind
+    if profiles_source.path().is_none() {
+        return;
+    }
+    // Only a convenience, so failing to remember the spot isn't fatal
+    if let Err(e) = get_selector_cursor_path().and_then(|path| write_selector_cursor(&path, name)) {
+        eprintln!("Warning: failed to remember the selector position: {e}");
+    }
+}
+
+/// Appends to the `--audit-log`, if one is configured. The log is bookkeeping,
+/// so failing to write it only warns.
+fn write_audit_entry(audit_log: Option<&Path>, profile: Option<&str>, backend: Option<&str>) {
+    if let Some(path) = audit_log
+        && let Err(e) = append_audit_entry(path, profile, backend)
+    {
+        eprintln!("Warning: failed to write the audit log: {e}");
+    }
+}
+
+/// Activates `profile`: exports its variables under `--current`, otherwise
+/// records it in the current_profile file. Also honors `--copy`, `--dry-run`,
+/// `--check` and `--login`.
+fn activate_profile(
+    matches: &ArgMatches,
+    profile: Profile,
+    profiles_source: &ProfilesSource,
+    current_profile_path: &Path,
+    shell: Option<ShellKind>,
+    audit_log: Option<&Path>,
+) -> Result<()> {
+    let dry_run = matches.get_flag("dry-run");
+    let quiet = matches.get_flag("quiet");
+
+    // Backends may reference environment variables, resolved only at activation
+    let profile = Profile {
+        backend: expand_backend(&profile.backend)?,
+        ..profile
+    };
+    // Pulumi treats an empty PULUMI_BACKEND_URL oddly rather than rejecting it
+    if profile.backend.trim().is_empty() {
+        return Err(anyhow::anyhow!(
+            "Profile '{}' has an empty backend; set one with --edit {}",
+            profile.name,
+            profile.name
+        ));
+    }
+    // The working directory of this run decides what a relative file:// backend means
+    let profile = if matches.get_flag("resolve-relative") {
+        Profile {
+            backend: resolve_file_backend(&profile.backend, &env::current_dir()?)?,
+            ..profile
+        }
+    } else {
+        profile
+    };
+
+    // Copying replaces activation, so nothing else is written
+    if matches.get_flag("copy") {
+        match copy_to_clipboard(&profile.backend) {
+            Ok(()) => eprintln!("Copied backend of profile '{}' to the clipboard", profile.name),
+            Err(e) => {
+                eprintln!("Warning: {e:#}; printing the backend instead");
+                println!("{}", profile.backend);
+            }
+        }
+        return Ok(());
+    }
+
+    if dry_run {
+        // Keep stdout empty so `eval "$(... -c --dry-run)"` changes nothing
+        eprintln!("Would activate profile '{}' ({})", profile.name, profile.backend);
+        return Ok(());
+    }
+
+    // Only the exports and `pulumi login` carry the token, so the keyring is left alone otherwise
+    let profile = if shell.is_some() || matches.get_flag("login") {
+        with_keyring_token(profile)?
+    } else {
+        profile
+    };
+
+    warn_if_passphrase_file_missing(&profile);
+    check_file_backend_dir(&profile, matches.get_flag("mkdir"), matches.get_flag("strict"))?;
+    if matches.get_flag("check") {
+        let secs = matches.get_one::<u64>("check-timeout").copied();
+        let timeout = Duration::from_secs(secs.unwrap_or(DEFAULT_CHECK_TIMEOUT_SECS));
+        check_reachability(&profile, timeout, matches.get_flag("strict"))?;
+    }
+    // A failed login leaves the previous activation in place
+    if matches.get_flag("login") {
+        pulumi_login(&profile, shell.is_some())?;
+    }
+
+    // Recency only affects ordering, so failing to record it shouldn't block activation.
+    // Inline and remote profiles run without touching the disk, e.g. in CI.
+    if let Some(profiles_path) = profiles_source.path() {
+        if let Err(e) = mark_profile_used(profiles_path, &profile.name) {
+            eprintln!("Warning: failed to record profile usage: {e}");
+        }
+        if let Err(e) = get_history_path().and_then(|path| record_activation(&path, &profile.name)) {
+            eprintln!("Warning: failed to record activation history: {e}");
+        }
+    }
+    write_audit_entry(audit_log, Some(&profile.name), Some(&profile.backend));
+
+    if let Some(shell) = shell {
+        // Output shell-specific export commands for the profile's variables
+        print_shell_command_for_profile(Some(&profile), shell)?;
+    } else {
+        // Only the name and backend are logged; the access token stays out of stdout
+        write_current_profile(current_profile_path, &profile.name, Some(&profile.backend))?;
+        info(quiet, format_args!("Pulumi profile activated: {} ({})", profile.name, profile.backend));
+    }
+
+    Ok(())
+}
+
+fn build_cli() -> Command {
+    Command::new("pulumi-profile-selector")
         .version("0.1.0")
         .author("Pulumi Profile Selector - Rust Edition")
         .about("Interactive Pulumi profile selector")
+        .after_help(
+            "Exit codes:\n  \
+             0  Success\n  \
+             1  No profiles available\n  \
+             2  Requested profile not found\n  \
+             3  Selection cancelled\n  \
+             4  Shell for --current output not recognized\n  \
+             5  --doctor found an error",
+        )
+        .arg(
+            Arg::new("activate")
+                .short('a')
+                .long("activate")
+                .help("Activate a profile by name or unique name prefix (skips interactive selection)")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("set-current")
+                .long("set-current")
+                .help("Write PROFILE to the current_profile file only, for tools that read it")
+                .value_name("PROFILE")
+                .conflicts_with_all([
+                    "current",
+                    "activate",
+                    "activate-index",
+                    "recent",
+                    "rotate",
+                    "rotate-back",
+                    "find-backend",
+                    "activate-glob",
+                    "filter",
+                ]),
+        )
+        .arg(
+            Arg::new("confirm")
+                .long("confirm")
+                .help("Show the full details of the profile picked in the selector and ask before activating it (off by default)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("mkdir")
+                .long("mkdir")
+                .help("Create a missing file:// backend directory when activating")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .help("Refuse to activate a file:// backend whose directory doesn't exist, or one failing --check")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .help("Check over the network that the backend exists before activating (reachability feature)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("copy")
+                .long("copy")
+                .help("Copy the chosen profile's backend URL to the clipboard instead of activating it (clipboard feature)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["current", "dry-run", "check"]),
+        )
+        .arg(
+            Arg::new("login")
+                .long("login")
+                .help("Run `pulumi login <backend>` for the chosen profile before activating it")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("copy"),
+        )
+        .arg(
+            Arg::new("check-timeout")
+                .long("check-timeout")
+                .help("Seconds to wait for the --check probe [default: 5]")
+                .value_name("SECS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("check"),
+        )
+        .arg(
+            Arg::new("activate-index")
+                .long("activate-index")
+                .help("Activate the profile shown as number N by --list with the same --sort (skips interactive selection)")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with_all(["activate", "recent", "find-backend", "tag"]),
+        )
+        .arg(
+            Arg::new("activate-glob")
+                .long("activate-glob")
+                .help("Activate the profile whose name matches PATTERN (* and ? wildcards), or pick among several matches")
+                .value_name("PATTERN")
+                .conflicts_with_all(["activate", "activate-index", "recent", "rotate", "rotate-back", "find-backend"]),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .help("Only offer profiles whose name or backend contains QUERY (ignoring case) in the selector")
+                .value_name("QUERY")
+                .conflicts_with_all(["activate", "activate-index", "recent", "find-backend"]),
+        )
+        .arg(
+            Arg::new("auto")
+                .long("auto")
+                .help("Activate without opening the selector when --filter leaves a single profile")
+                .action(ArgAction::SetTrue)
+                .requires("filter"),
+        )
+        .arg(
+            Arg::new("recent")
+                .long("recent")
+                .help("Activate the most recently used profile (skips interactive selection)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .visible_alias("loop")
+                .help("Show the selector again after each activation, until it is cancelled with Esc or Ctrl-C")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "current",
+                    "activate",
+                    "activate-index",
+                    "recent",
+                    "rotate",
+                    "rotate-back",
+                    "find-backend",
+                    "activate-glob",
+                    "auto",
+                    "copy",
+                ]),
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .help("Append a line (time, user, profile, backend) to PATH on every activation and deactivation")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("resolve-relative")
+                .long("resolve-relative")
+                .help("Make a relative file:// backend absolute against the working directory before activating")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rotate")
+                .long("rotate")
+                .help("Activate the profile after the active one in --sort order, wrapping around")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["activate", "activate-index", "recent", "find-backend", "tag", "filter"]),
+        )
+        .arg(
+            Arg::new("rotate-back")
+                .long("rotate-back")
+                .help("Activate the profile before the active one in --sort order, wrapping around")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["rotate", "activate", "activate-index", "recent", "find-backend", "tag", "filter"]),
+        )
         .arg(
-            Arg::new("activate")
-                .short('a')
-                .long("activate")
-                .help("Activate a specific profile by name (skips interactive selection)")
-                .value_name("PROFILE"),
+            Arg::new("find-backend")
+                .long("find-backend")
+                .help("Activate a profile whose backend contains SUBSTRING, picking among several matches")
+                .value_name("SUBSTRING")
+                .conflicts_with_all(["activate", "recent", "tag"]),
         )
         .arg(
             Arg::new("deactivate")
@@ -40,23 +1280,209 @@ fn main() -> Result<()> {
                 .help("Output the profile name only (for setting in current shell)")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("print-env")
+                .long("print-env")
+                .help("Print a profile's environment as KEY=VALUE lines (e.g. for docker --env-file)")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("export-env-file")
+                .long("export-env-file")
+                .help("Write a profile's environment as KEY=VALUE lines to --out (or stdout), e.g. a docker-compose .env")
+                .value_name("PROFILE")
+                .conflicts_with("print-env"),
+        )
+        .arg(
+            Arg::new("out")
+                .long("out")
+                .help("File --export-env-file writes to, replacing it")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("export-env-file"),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .help("Start the --export-env-file output with comments naming the profile and the generation time")
+                .action(ArgAction::SetTrue)
+                .requires("export-env-file"),
+        )
+        .arg(
+            Arg::new("shell")
+                .long("shell")
+                .help("Shell syntax for --current output, overriding detection from $SHELL")
+                .value_name("SHELL")
+                .value_parser(clap::builder::PossibleValuesParser::new(SHELL_NAMES.iter().copied())),
+        )
+        .arg(
+            Arg::new("assume-posix")
+                .long("assume-posix")
+                .help("Emit POSIX syntax with --current when $SHELL isn't recognized, instead of failing")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("add")
                 .long("add")
                 .help("Add a new profile interactively")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("name")
+                .long("name")
+                .help("Name for the profile created by --add")
+                .value_name("NAME")
+                .requires("add"),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .help("Backend URL for --add, or the new backend URL for --edit")
+                .value_name("URL")
+                .requires("backend-target"),
+        )
+        .group(ArgGroup::new("backend-target").args(["add", "edit"]).multiple(true))
+        .group(
+            ArgGroup::new("local-target")
+                .args([
+                    "add",
+                    "edit",
+                    "delete",
+                    "rename",
+                    "clone",
+                    "import",
+                    "import-credentials",
+                    "dedupe",
+                    "replace-in-backends",
+                    "repair",
+                    "set-token",
+                    "clear-token",
+                    "apply-template",
+                ])
+                .multiple(true),
+        )
         .arg(
             Arg::new("edit")
                 .long("edit")
                 .help("Edit an existing profile's backend URL")
                 .value_name("PROFILE"),
         )
+        .arg(
+            Arg::new("export")
+                .long("export")
+                .help("Write all profiles to a portable JSON file")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("import")
+                .long("import")
+                .help("Merge profiles from a file written by --export")
+                .value_name("FILE")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("apply-template")
+                .long("apply-template")
+                .help("Create the profiles of a template in templates.json, skipping names already taken")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("var")
+                .long("var")
+                .help("Value for a {{KEY}} placeholder in --apply-template (repeatable)")
+                .value_name("KEY=VALUE")
+                .value_parser(parse_template_var)
+                .action(ArgAction::Append)
+                .requires("apply-template"),
+        )
+        .arg(
+            Arg::new("on-conflict")
+                .long("on-conflict")
+                .help("How --import handles names that already exist")
+                .value_name("STRATEGY")
+                .value_parser(["skip", "overwrite", "rename"])
+                .default_value("skip"),
+        )
+        .arg(
+            Arg::new("import-credentials")
+                .long("import-credentials")
+                .help("Create profiles for backends found in Pulumi's credentials.json")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("clone")
+                .long("clone")
+                .help("Copy a profile under a new name, then offer to change its backend URL")
+                .num_args(2)
+                .value_names(["SOURCE", "NEW_NAME"]),
+        )
+        .arg(
+            Arg::new("pin")
+                .long("pin")
+                .help("Pin a profile to the top of the selector, unpinning any other")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("set-default")
+                .long("set-default")
+                .help("Make a profile the one --current activates when no profile is given")
+                .value_name("NAME"),
+        )
+        .arg(
+            Arg::new("set-token")
+                .long("set-token")
+                .help("Store a profile's access token in the OS keyring instead of profiles.json (keyring feature)")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("clear-token")
+                .long("clear-token")
+                .help("Remove a profile's access token from the OS keyring (keyring feature)")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("unpin")
+                .long("unpin")
+                .help("Unpin a profile")
+                .value_name("PROFILE"),
+        )
+        .arg(
+            Arg::new("rename")
+                .long("rename")
+                .help("Rename a profile, keeping its backend URL")
+                .num_args(2)
+                .value_names(["OLD", "NEW"]),
+        )
         .arg(
             Arg::new("delete")
                 .long("delete")
-                .help("Delete a profile")
-                .value_name("PROFILE"),
+                .help("Delete a profile (without a name, pick several interactively)")
+                .value_name("PROFILE")
+                .num_args(0..=1),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("Skip confirmation prompts")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .visible_alias("last")
+                .help("Print the last N activated profiles, newest first [default: 10]")
+                .value_name("N")
+                .num_args(0..=1)
+                .default_missing_value(DEFAULT_HISTORY_COUNT)
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("count")
+                .long("count")
+                .help("Print the number of profiles")
+                .action(ArgAction::SetTrue),
         )
         .arg(
             Arg::new("list")
@@ -65,191 +1491,517 @@ fn main() -> Result<()> {
                 .help("List all profiles")
                 .action(ArgAction::SetTrue),
         )
-        .get_matches();
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .help("Back up an unparseable profiles file to profiles.json.bak and reset it")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedupe")
+                .long("dedupe")
+                .help("Remove profiles whose name repeats an earlier one, keeping the first")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("replace-in-backends")
+                .long("replace-in-backends")
+                .help("Replace FROM with TO in every profile's backend URL, saving once")
+                .num_args(2)
+                .value_names(["FROM", "TO"]),
+        )
+        .arg(
+            Arg::new("which")
+                .long("which")
+                .help("Print the profiles and current_profile paths in use and whether they exist")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("doctor")
+                .long("doctor")
+                .help("Check the profiles, the active profile and shell detection for common problems")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("status")
+                .long("status")
+                .help("Show the active profile and its backend URL")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tag")
+                .long("tag")
+                .help("Only offer profiles with this tag in the selector (repeat to require several)")
+                .value_name("TAG")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("page-size")
+                .long("page-size")
+                .help("Number of profiles shown at once in the selector [default: 10]")
+                .value_name("N")
+                .env("PPS_PAGE_SIZE")
+                .value_parser(parse_page_size),
+        )
+        .arg(
+            Arg::new("backups")
+                .long("backups")
+                .help("Number of profiles.json backups to keep when changing profiles, 0 to disable [default: 1]")
+                .value_name("N")
+                .global(true)
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("local")
+                .long("local")
+                .help("Write the change to profiles.local.json, next to profiles.json, instead")
+                .requires("local-target")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("Skip backend URL validation on add/edit, or allow deleting the active profile")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format for --list [default: table]")
+                .value_name("FORMAT")
+                .value_parser(clap::builder::PossibleValuesParser::new(LIST_FORMATS.iter().copied())),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("Order of --list and the selector: name, backend, recent or none (file order) [default: name]")
+                .value_name("KEY")
+                .value_parser(clap::builder::PossibleValuesParser::new(SORT_KEYS.iter().copied())),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Report what --add, --edit, --rename, --delete or activation would change without writing anything")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "clone",
+                    "pin",
+                    "unpin",
+                    "set-default",
+                    "import",
+                    "import-credentials",
+                    "repair",
+                    "deactivate",
+                    "new",
+                ]),
+        )
+        .arg(
+            Arg::new("no-create")
+                .long("no-create")
+                .help("Don't create an empty profiles file when it is missing")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("case-sensitive")
+                .long("case-sensitive")
+                .help("Allow profile names that differ only in case, such as Prod and prod")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Don't print confirmations such as \"Pulumi profile activated\"; errors and command output still appear")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Log the files read and written, and the resolved shell, to stderr")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .help("When to color the selector, --list and --status: auto (a terminal without NO_COLOR), always or never [default: auto]")
+                .value_name("WHEN")
+                .global(true)
+                .value_parser(clap::builder::PossibleValuesParser::new(COLOR_CHOICES.iter().copied())),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .help("Disable colored output, like --color never")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .conflicts_with("color"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .help("Use an alternate profiles file instead of the default profiles.json")
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .global(true),
+        )
+        .arg(
+            Arg::new("profiles-url")
+                .long("profiles-url")
+                .help("Read the profiles from a shared profiles.json served over HTTP; they can't be modified (remote feature)")
+                .value_name("URL")
+                .global(true)
+                .conflicts_with("config"),
+        )
+        .arg(
+            Arg::new("profiles-timeout")
+                .long("profiles-timeout")
+                .help("Seconds to wait for --profiles-url to answer [default: 10]")
+                .value_name("SECS")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .requires("profiles-url"),
+        )
+        .arg(
+            Arg::new("init")
+                .long("init")
+                .help("Print a `pps` shell function that applies --current output, for sourcing in your shell's rc file")
+                .value_name("SHELL")
+                .value_parser(clap::builder::PossibleValuesParser::new(INIT_SHELLS.iter().copied())),
+        )
+        .arg(
+            Arg::new("json-schema")
+                .long("json-schema")
+                .help("Print a JSON Schema for profiles.json, for validating it in CI")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("generate-completions")
+                .long("generate-completions")
+                .help("Print a shell completion script to stdout")
+                .value_name("SHELL")
+                .value_parser(["bash", "zsh", "fish", "nushell"])
+                .hide(true),
+        )
+        .arg(
+            Arg::new("complete-profiles")
+                .long("complete-profiles")
+                .help("Print profile names one per line for shell completion")
+                .action(ArgAction::SetTrue)
+                .hide(true),
+        )
+}
 
-    let current_profile_path = get_current_profile_path()?;
-    let current_shell_mode = matches.get_flag("current");
+/// Reads the profiles, creating an empty profiles file unless `--no-create` was given.
+/// Group members come back with their full backend, ready to show or activate.
+fn read_profiles(profiles_source: &ProfilesSource, no_create: bool) -> Result<Vec<Profile>> {
+    let profiles = if no_create { profiles_source.load()? } else { profiles_source.read()? };
 
-    // Handle profile management commands first
-    if matches.get_flag("add") {
-        let (name, backend) = prompt_for_profile_details()?;
-        add_profile(name.clone(), backend)?;
-        println!("Profile '{}' added successfully", name);
-        return Ok(());
+    // Lookups take the first match, so later duplicates are silently ignored
+    let duplicates = find_duplicate_names(&profiles);
+    if !duplicates.is_empty() {
+        eprintln!(
+            "Warning: duplicate profile names only use the first entry: {} (run --dedupe to remove the rest)",
+            duplicates.join(", ")
+        );
     }
 
-    if let Some(profile_name) = matches.get_one::<String>("edit") {
-        let new_backend = prompt_for_backend_url()?;
-        edit_profile(profile_name, new_backend)?;
-        println!("Profile '{}' updated successfully", profile_name);
-        return Ok(());
+    let groups = profiles_source.load_groups()?;
+    for profile in &profiles {
+        if let Some(group) = &profile.group
+            && !groups.iter().any(|g| &g.name == group)
+        {
+            eprintln!("Warning: profile '{}' refers to undefined group '{}'", profile.name, group);
+        }
+        for (name, value) in &profile.env {
+            if let Err(e) = check_extra_env_var(name, value) {
+                eprintln!("Warning: profile '{}' skips an extra variable: {e}", profile.name);
+            }
+        }
     }
+    Ok(resolve_group_backends(profiles, &groups))
+}
 
-    if let Some(profile_name) = matches.get_one::<String>("delete") {
-        delete_profile(profile_name)?;
-        println!("Profile '{}' deleted successfully", profile_name);
-        return Ok(());
+/// Prints a confirmation to stdout unless `--quiet` was given. Command output
+/// such as `--list` and `--current` exports is printed regardless.
+fn info(quiet: bool, msg: impl fmt::Display) {
+    if !quiet {
+        println!("{msg}");
     }
+}
 
-    if matches.get_flag("list") {
-        let profiles = read_pulumi_profiles()?;
-        if profiles.is_empty() {
-            println!("No profiles found.");
-        } else {
-            println!("Available profiles:");
-            for profile in &profiles {
-                println!("  {} -> {}", profile.name, profile.backend);
-            }
+fn print_shell_command(
+    profiles_source: &ProfilesSource,
+    current_profile_path: &Path,
+    profile_name: Option<&str>,
+    shell: ShellKind,
+) -> Result<()> {
+    // This function is for backwards compatibility, but for Pulumi we need the backend URL
+    // We'll read the current profile to get the backend URL
+    if let Some(name) = profile_name {
+        if let Ok(profiles) = profiles_source.load()
+            && let Some(profile) = profiles.iter().find(|p| p.name == name)
+        {
+            let groups = profiles_source.load_groups()?;
+            let profile = Profile {
+                backend: expand_backend(&profile.full_backend(&groups))?,
+                ..profile.clone()
+            };
+            print_shell_command_for_profile(Some(&profile), shell)?;
+            return Ok(());
         }
-        return Ok(());
+        // Fallback: the backend it was last activated with, or else just the
+        // profile name (this shouldn't happen in normal usage)
+        let recorded = read_current_profile_entry(current_profile_path)
+            .ok()
+            .flatten()
+            .filter(|current| current.name == name)
+            .and_then(|current| current.backend);
+        let unknown = Profile::new(name.to_string(), recorded.unwrap_or_else(|| name.to_string()));
+        print_shell_command_for_profile(Some(&unknown), shell)?;
+    } else {
+        // Unset extra variables from every profile, since any of them may be active
+        let profiles = profiles_source.load().unwrap_or_default();
+        let names: Vec<&str> = MANAGED_ENV_VARS
+            .iter()
+            .copied()
+            .chain(extra_env_var_names(&profiles))
+            .collect();
+        print!("{}", shell.unset_vars_command(&names));
     }
 
-    // Handle deactivation
-    if matches.get_flag("deactivate") {
-        if current_shell_mode {
-            // Output shell-specific unset command
-            print_shell_command(None);
-        } else {
-            if current_profile_path.exists() {
-                std::fs::remove_file(&current_profile_path)?;
-                println!("Pulumi profile deactivated");
-            } else {
-                println!("No active Pulumi profile to deactivate");
-            }
-        }
-        return Ok(());
+    Ok(())
+}
+
+fn parse_template_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err("expected KEY=VALUE".to_string()),
     }
+}
 
-    // Handle new profile (doesn't require reading existing profiles)
-    if let Some(profile_name) = matches.get_one::<String>("new") {
-        if current_shell_mode {
-            // Output shell-specific export command
-            print_shell_command(Some(profile_name));
-        } else {
-            // Create .pulumi directory if it doesn't exist
-            if let Some(parent) = current_profile_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            
-            // Write profile name to file
-            std::fs::write(&current_profile_path, profile_name)?;
-            println!("Pulumi profile activated: {profile_name}");
-        }
-        return Ok(());
+fn parse_page_size(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("page size must be at least 1".to_string()),
+        Ok(n) => Ok(n.min(MAX_PAGE_SIZE)),
+        Err(e) => Err(e.to_string()),
     }
+}
 
-    let profiles = read_pulumi_profiles()?;
+/// The profile `--rotate` steps from. Under `--current` that's the profile
+/// whose backend this shell exports, since nothing is written to disk there;
+/// otherwise it's the one in the current_profile file.
+fn active_profile_name(
+    profiles: &[Profile],
+    current_profile_path: &Path,
+    current_shell_mode: bool,
+) -> Result<Option<String>> {
+    if current_shell_mode {
+        let exported = env::var("PULUMI_BACKEND_URL").is_ok_and(|backend| !backend.is_empty());
+        return Ok(profiles
+            .iter()
+            .find(|p| exported && env_backend_matches(p))
+            .map(|p| p.name.clone()));
+    }
+    Ok(read_current_profile(current_profile_path)?)
+}
 
-    if profiles.is_empty() {
-        eprintln!("No Pulumi profiles found in ~/.pulumi/profiles.json");
-        eprintln!("Use --add to create your first profile");
-        std::process::exit(1);
+fn exit_profile_not_found(profile_name: &str, profiles: &[Profile]) -> ! {
+    eprintln!("Profile '{}' not found in Pulumi profiles", profile_name);
+    eprintln!("Available profiles:");
+    for profile in profiles {
+        eprintln!("  {}", profile.name);
     }
+    std::process::exit(EXIT_PROFILE_NOT_FOUND);
+}
 
-    // Handle direct profile activation
-    let selected_profile = if let Some(profile_name) = matches.get_one::<String>("activate") {
-        // Validate that the profile exists and get its backend URL
-        if let Some(profile) = profiles.iter().find(|p| &p.name == profile_name) {
-            Some((profile.name.clone(), profile.backend.clone()))
-        } else {
-            eprintln!("Profile '{}' not found in Pulumi profiles", profile_name);
-            eprintln!("Available profiles:");
-            for profile in &profiles {
-                eprintln!("  {}", profile.name);
-            }
-            std::process::exit(1);
-        }
-    } else {
-        // Run interactive selector
-        let mut selector = ProfileSelector::new(profiles.clone());
-        if let Some(selected_name) = selector.run()? {
-            if let Some(profile) = profiles.iter().find(|p| p.name == selected_name) {
-                Some((profile.name.clone(), profile.backend.clone()))
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+/// Pulumi's own error for a missing `file://` state directory is confusing,
+/// so catch it at activation: warn, create it with `--mkdir`, or fail with `--strict`.
+fn check_file_backend_dir(profile: &Profile, mkdir: bool, strict: bool) -> Result<()> {
+    let Some(dir) = file_backend_path(&profile.backend) else {
+        return Ok(());
     };
+    if dir.is_dir() {
+        return Ok(());
+    }
 
-    match selected_profile {
-        Some((profile_name, backend_url)) => {
-            if current_shell_mode {
-                // Output shell-specific export command with backend URL
-                print_shell_command_with_backend(Some(&backend_url));
-            } else {
-                // Create .pulumi directory if it doesn't exist
-                if let Some(parent) = current_profile_path.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
-                
-                // Write profile name to file
-                std::fs::write(&current_profile_path, &profile_name)?;
-                println!("Pulumi profile activated: {} ({})", profile_name, backend_url);
-            }
+    if mkdir {
+        log::debug!("Creating backend directory {}", dir.display());
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create backend directory {}", dir.display()))?;
+        eprintln!("Created backend directory {}", dir.display());
+    } else if strict {
+        return Err(anyhow::anyhow!(
+            "Backend directory for profile '{}' does not exist: {} (use --mkdir to create it)",
+            profile.name,
+            dir.display()
+        ));
+    } else {
+        eprintln!(
+            "Warning: backend directory for profile '{}' does not exist: {} (use --mkdir to create it)",
+            profile.name,
+            dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Probes the backend over the network so a deleted bucket or wrong region
+/// shows up before Pulumi trips over it. Only fails with `--strict`.
+#[cfg(feature = "reachability")]
+fn check_reachability(profile: &Profile, timeout: Duration, strict: bool) -> Result<()> {
+    match check_backend(&profile.backend, timeout) {
+        CheckOutcome::Reachable => log::debug!("Backend {} is reachable", profile.backend),
+        CheckOutcome::Skipped(reason) => {
+            eprintln!("Backend of profile '{}' not checked: {}", profile.name, reason)
         }
-        None => {
-            println!("No profile selected");
-            std::process::exit(1);
+        CheckOutcome::Failed(reason) if strict => {
+            return Err(anyhow::anyhow!("Backend check failed for profile '{}': {}", profile.name, reason));
+        }
+        CheckOutcome::Failed(reason) => {
+            eprintln!("Warning: backend check failed for profile '{}': {}", profile.name, reason)
         }
     }
 
     Ok(())
 }
 
-fn get_current_profile_path() -> Result<PathBuf> {
-    let home_dir = dirs::home_dir()
-        .ok_or_else(|| anyhow::anyhow!("Unable to determine home directory"))?;
-    
-    Ok(home_dir.join(".pulumi").join("current_profile"))
+/// Fetches the shared profiles, which then stand in for the profiles file
+/// for the rest of the run without being written anywhere.
+#[cfg(feature = "remote")]
+fn fetch_remote_profiles(url: &str, timeout: Duration) -> Result<String> {
+    Ok(fetch_profiles(url, timeout)?)
 }
 
-fn print_shell_command(profile_name: Option<&str>) {
-    // This function is for backwards compatibility, but for Pulumi we need the backend URL
-    // We'll read the current profile to get the backend URL
-    if let Some(name) = profile_name {
-        if let Ok(profiles) = read_pulumi_profiles() {
-            if let Some(profile) = profiles.iter().find(|p| &p.name == name) {
-                print_shell_command_with_backend(Some(&profile.backend));
-                return;
-            }
-        }
-        // Fallback: just print the profile name (this shouldn't happen in normal usage)
-        print_shell_command_with_backend(Some(name));
-    } else {
-        print_shell_command_with_backend(None);
+#[cfg(not(feature = "remote"))]
+fn fetch_remote_profiles(_url: &str, _timeout: Duration) -> Result<String> {
+    Err(anyhow::anyhow!(
+        "--profiles-url needs a build with the 'remote' feature (cargo install --features remote)"
+    ))
+}
+
+#[cfg(not(feature = "reachability"))]
+fn check_reachability(_profile: &Profile, _timeout: Duration, _strict: bool) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "--check needs a build with the 'reachability' feature (cargo install --features reachability)"
+    ))
+}
+
+/// Fills in the access token of a profile that keeps it in the OS keyring,
+/// for the commands that export it.
+fn with_keyring_token(profile: Profile) -> Result<Profile> {
+    if !(profile.access_token_in_keyring && profile.is_cloud_backend()) {
+        return Ok(profile);
     }
+    Ok(Profile {
+        access_token: Some(keyring_token(&profile.name)?),
+        ..profile
+    })
 }
 
-fn print_shell_command_with_backend(backend_url: Option<&str>) {
-    // Detect the shell from SHELL environment variable
-    let shell = std::env::var("SHELL").unwrap_or_default();
-    
-    match backend_url {
-        Some(url) => {
-            if shell.contains("nu") || shell.contains("nushell") {
-                // Nushell syntax
-                print!("$env.PULUMI_BACKEND_URL = \"{}\"", url);
-            } else if shell.contains("fish") {
-                // Fish syntax
-                print!("set -gx PULUMI_BACKEND_URL \"{}\"", url);
-            } else {
-                // Default to bash/zsh/POSIX syntax
-                print!("export PULUMI_BACKEND_URL=\"{}\"", url);
-            }
+#[cfg(feature = "keyring")]
+fn ensure_keyring_support(_flag: &str) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn ensure_keyring_support(flag: &str) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "{flag} needs a build with the 'keyring' feature (cargo install --features keyring)"
+    ))
+}
+
+#[cfg(feature = "keyring")]
+fn store_keyring_token(name: &str, token: &str) -> Result<()> {
+    Ok(secrets::store_token(name, token)?)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn store_keyring_token(_name: &str, _token: &str) -> Result<()> {
+    ensure_keyring_support("--set-token")
+}
+
+#[cfg(feature = "keyring")]
+fn delete_keyring_token(name: &str) -> Result<()> {
+    Ok(secrets::delete_token(name)?)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn delete_keyring_token(_name: &str) -> Result<()> {
+    ensure_keyring_support("--clear-token")
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_token(name: &str) -> Result<String> {
+    Ok(secrets::fetch_token(name)?)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_token(name: &str) -> Result<String> {
+    Err(anyhow::anyhow!(
+        "Profile '{name}' keeps its access token in the OS keyring, which needs a build with the 'keyring' feature (cargo install --features keyring)"
+    ))
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .context("couldn't copy to the clipboard")
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "--copy needs a build with the 'clipboard' feature (cargo install --features clipboard)"
+    ))
+}
+
+fn warn_if_passphrase_file_missing(profile: &Profile) {
+    if let Some(path) = &profile.passphrase_file
+        && !path.exists()
+    {
+        eprintln!(
+            "Warning: passphrase file for profile '{}' does not exist: {}",
+            profile.name,
+            path.display()
+        );
+    }
+}
+
+/// Detects the shell for `--current` output. When it can't be recognized,
+/// POSIX syntax is only used with `--assume-posix`; otherwise this exits so a
+/// wrapper's eval doesn't run commands in the wrong syntax.
+fn resolve_shell(shell_override: Option<&str>, assume_posix: bool) -> ShellKind {
+    match detect_shell(shell_override) {
+        Ok(shell) => {
+            log::debug!("Resolved shell: {shell:?}");
+            shell
         }
-        None => {
-            if shell.contains("nu") || shell.contains("nushell") {
-                // Nushell syntax for unsetting
-                print!("hide-env PULUMI_BACKEND_URL");
-            } else if shell.contains("fish") {
-                // Fish syntax for unsetting
-                print!("set -e PULUMI_BACKEND_URL");
-            } else {
-                // Default to bash/zsh/POSIX syntax
-                print!("unset PULUMI_BACKEND_URL");
-            }
+        Err(e) if assume_posix => {
+            log::debug!("{e}; assuming POSIX");
+            ShellKind::Posix
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            eprintln!("Pass --shell <SHELL>, or --assume-posix to emit POSIX syntax");
+            std::process::exit(EXIT_UNKNOWN_SHELL);
+        }
+    }
+}
+
+fn print_shell_command_for_profile(profile: Option<&Profile>, shell: ShellKind) -> Result<()> {
+    match profile {
+        Some(profile) => {
+            let vars = profile.env_vars();
+            check_shell_values(&vars)?;
+            print!("{}", shell.set_vars_command(&vars));
         }
+        None => print!("{}", shell.unset_managed_vars_command()),
     }
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! Network checks that a backend exists before it is activated, behind the
+//! `reachability` feature.
+
+use std::env;
+use std::time::Duration;
+
+const AWS_CREDENTIAL_VARS: &[&str] = &["AWS_ACCESS_KEY_ID", "AWS_PROFILE", "AWS_WEB_IDENTITY_TOKEN_FILE"];
+const GOOGLE_CREDENTIAL_VARS: &[&str] = &["GOOGLE_APPLICATION_CREDENTIALS", "GOOGLE_CREDENTIALS"];
+const AZURE_CREDENTIAL_VARS: &[&str] = &["AZURE_STORAGE_KEY", "AZURE_STORAGE_SAS_TOKEN"];
+
+/// What `check_backend` found out about a backend.
+#[derive(Debug, PartialEq)]
+pub enum CheckOutcome {
+    /// The backend answered and nothing looked wrong
+    Reachable,
+    /// The backend is missing, misconfigured or didn't answer in time
+    Failed(String),
+    /// The backend can't be checked, e.g. a `file://` backend or missing credentials
+    Skipped(String),
+}
+
+/// One HTTP probe and how to read its answer.
+#[derive(Debug, PartialEq)]
+struct Probe {
+    url: String,
+    /// A 404 means the bucket or container doesn't exist
+    missing_on_404: bool,
+    /// The region the profile expects an S3 bucket in
+    expected_region: Option<String>,
+}
+
+/// Probes the backend with a single HTTP HEAD, giving up after `timeout`.
+/// Cloud backends only need to answer; object stores must also exist.
+pub fn check_backend(backend: &str, timeout: Duration) -> CheckOutcome {
+    let probe = match plan_probe(backend, &|name| env::var(name).ok()) {
+        Ok(probe) => probe,
+        Err(reason) => return CheckOutcome::Skipped(reason),
+    };
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(timeout))
+        .http_status_as_error(false)
+        // S3 answers a bucket in another region with a redirect naming the region
+        .max_redirects(0)
+        .build()
+        .into();
+
+    log::debug!("Checking {} with HEAD {}", backend, probe.url);
+    match agent.head(&probe.url).call() {
+        Ok(response) => {
+            let region = response
+                .headers()
+                .get("x-amz-bucket-region")
+                .and_then(|value| value.to_str().ok());
+            judge(&probe, response.status().as_u16(), region)
+        }
+        Err(ureq::Error::Timeout(_)) => {
+            CheckOutcome::Failed(format!("no answer from {} within {}s", probe.url, timeout.as_secs_f32()))
+        }
+        Err(e) => CheckOutcome::Failed(format!("{} is unreachable: {e}", probe.url)),
+    }
+}
+
+fn judge(probe: &Probe, status: u16, region: Option<&str>) -> CheckOutcome {
+    if probe.missing_on_404 && status == 404 {
+        return CheckOutcome::Failed(format!("{} does not exist (HTTP 404)", probe.url));
+    }
+    if status >= 500 {
+        return CheckOutcome::Failed(format!("{} answered HTTP {status}", probe.url));
+    }
+    if let (Some(expected), Some(actual)) = (&probe.expected_region, region)
+        && expected != actual
+    {
+        return CheckOutcome::Failed(format!(
+            "bucket is in region {actual}, but the backend asks for {expected}"
+        ));
+    }
+
+    // 401 and 403 still prove the bucket exists; the real credentials are Pulumi's concern
+    CheckOutcome::Reachable
+}
+
+/// Works out which URL to probe for `backend`, or why it can't be checked.
+/// `lookup` reads an environment variable.
+fn plan_probe(backend: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Probe, String> {
+    let is_set = |name: &&str| lookup(name).is_some_and(|value| !value.is_empty());
+    let Some((scheme, rest)) = backend.split_once("://") else {
+        return Err("backend has no scheme".to_string());
+    };
+    let (location, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let bucket = location.split('/').next().unwrap_or_default();
+    let param = |key: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.to_string())
+    };
+
+    let store = |url: String, expected_region: Option<String>| {
+        Ok(Probe {
+            url,
+            missing_on_404: true,
+            expected_region,
+        })
+    };
+
+    match scheme {
+        "https" | "http" => Ok(Probe {
+            url: backend.to_string(),
+            missing_on_404: false,
+            expected_region: None,
+        }),
+        "s3" if param("endpoint").is_some() => Err("custom S3 endpoints are not checked".to_string()),
+        "s3" if !AWS_CREDENTIAL_VARS.iter().any(is_set) => {
+            Err("no AWS credentials in the environment".to_string())
+        }
+        "s3" => store(format!("https://{bucket}.s3.amazonaws.com/"), param("region")),
+        "gs" if !GOOGLE_CREDENTIAL_VARS.iter().any(is_set) => {
+            Err("no Google Cloud credentials in the environment".to_string())
+        }
+        "gs" => store(format!("https://storage.googleapis.com/{bucket}"), None),
+        "azblob" => match lookup("AZURE_STORAGE_ACCOUNT").filter(|account| !account.is_empty()) {
+            // Anonymous requests can't tell a private container from a missing one,
+            // so only the storage account is checked
+            Some(account) if AZURE_CREDENTIAL_VARS.iter().any(is_set) => Ok(Probe {
+                url: format!("https://{account}.blob.core.windows.net/"),
+                missing_on_404: false,
+                expected_region: None,
+            }),
+            _ => Err("no Azure storage account credentials in the environment".to_string()),
+        },
+        _ => Err(format!("{scheme}:// backends are not checked over the network")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn env_with<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn test_plan_probe() {
+        let aws = env_with(&[("AWS_PROFILE", "dev")]);
+        let probe = plan_probe("s3://state-bucket/infra?region=eu-west-1", &aws).unwrap();
+        assert_eq!(probe.url, "https://state-bucket.s3.amazonaws.com/");
+        assert!(probe.missing_on_404);
+        assert_eq!(probe.expected_region.as_deref(), Some("eu-west-1"));
+
+        let none = env_with(&[]);
+        assert!(plan_probe("s3://state-bucket", &none).is_err(), "needs credentials");
+        assert!(plan_probe("s3://b?endpoint=minio:9000", &aws).is_err());
+        assert!(plan_probe("file://~", &aws).is_err());
+
+        let probe = plan_probe("https://api.pulumi.com", &none).unwrap();
+        assert_eq!(probe.url, "https://api.pulumi.com");
+        assert!(!probe.missing_on_404);
+
+        let google = env_with(&[("GOOGLE_APPLICATION_CREDENTIALS", "/key.json")]);
+        assert_eq!(plan_probe("gs://state/x", &google).unwrap().url, "https://storage.googleapis.com/state");
+
+        let azure = env_with(&[("AZURE_STORAGE_ACCOUNT", "acct"), ("AZURE_STORAGE_KEY", "k")]);
+        assert_eq!(plan_probe("azblob://state", &azure).unwrap().url, "https://acct.blob.core.windows.net/");
+        assert!(plan_probe("azblob://state", &env_with(&[("AZURE_STORAGE_KEY", "k")])).is_err());
+    }
+
+    #[test]
+    fn test_judge_statuses() {
+        let bucket = Probe {
+            url: "https://b.s3.amazonaws.com/".to_string(),
+            missing_on_404: true,
+            expected_region: Some("eu-west-1".to_string()),
+        };
+        assert_eq!(judge(&bucket, 403, Some("eu-west-1")), CheckOutcome::Reachable);
+        assert!(matches!(judge(&bucket, 404, None), CheckOutcome::Failed(_)));
+        assert!(matches!(judge(&bucket, 301, Some("us-east-1")), CheckOutcome::Failed(reason) if reason.contains("us-east-1")));
+
+        let cloud = Probe {
+            url: "https://api.pulumi.com".to_string(),
+            missing_on_404: false,
+            expected_region: None,
+        };
+        assert_eq!(judge(&cloud, 404, None), CheckOutcome::Reachable);
+        assert!(matches!(judge(&cloud, 503, None), CheckOutcome::Failed(_)));
+    }
+
+    #[test]
+    fn test_check_backend_against_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n").unwrap();
+        });
+
+        let outcome = check_backend(&url, Duration::from_secs(5));
+        server.join().unwrap();
+        assert!(matches!(outcome, CheckOutcome::Failed(reason) if reason.contains("503")));
+
+        // Nothing listens on a port that was just released
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let outcome = check_backend(&format!("http://127.0.0.1:{port}/"), Duration::from_secs(5));
+        assert!(matches!(outcome, CheckOutcome::Failed(_)));
+    }
+}
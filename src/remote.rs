@@ -0,0 +1,91 @@
+//! A shared profiles.json fetched over HTTP for `--profiles-url`, behind the
+//! `remote` feature.
+
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RemoteError {
+    #[error("No answer from {url} within {}s", .timeout.as_secs_f32())]
+    Timeout { url: String, timeout: Duration },
+    #[error("{url} answered HTTP {status}")]
+    Status { url: String, status: u16 },
+    #[error("Failed to fetch {url}: {message}")]
+    Request { url: String, message: String },
+}
+
+impl RemoteError {
+    fn from_ureq(url: &str, timeout: Duration, error: ureq::Error) -> Self {
+        match error {
+            ureq::Error::Timeout(_) => RemoteError::Timeout { url: url.to_string(), timeout },
+            other => RemoteError::Request { url: url.to_string(), message: other.to_string() },
+        }
+    }
+}
+
+/// Fetches the profiles file at `url` with a single GET, giving up after
+/// `timeout`. The body is returned unparsed; anything but a 2xx is an error.
+pub fn fetch_profiles(url: &str, timeout: Duration) -> Result<String, RemoteError> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(timeout))
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    log::debug!("Fetching profiles from {url}");
+    let mut response = agent
+        .get(url)
+        .call()
+        .map_err(|e| RemoteError::from_ureq(url, timeout, e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(RemoteError::Status { url: url.to_string(), status: status.as_u16() });
+    }
+
+    response
+        .body_mut()
+        .read_to_string()
+        .map_err(|e| RemoteError::from_ureq(url, timeout, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProfilesFile;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers one request on a local port with `response`, returning its URL.
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/profiles.json", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_fetched_profiles_parse() {
+        const BODY: &str = r#"{"version": 1, "profiles": [{"name": "shared", "backend": "s3://team-state"}]}"#;
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{BODY}", BODY.len());
+        let (url, server) = serve_once(response.leak());
+
+        let content = fetch_profiles(&url, Duration::from_secs(5)).unwrap();
+        server.join().unwrap();
+        let file: ProfilesFile = serde_json::from_str(&content).unwrap();
+        assert_eq!(file.profiles[0].name, "shared");
+        assert_eq!(file.profiles[0].backend, "s3://team-state");
+    }
+
+    #[test]
+    fn test_error_statuses_fail() {
+        let (url, server) = serve_once("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        let error = fetch_profiles(&url, Duration::from_secs(5)).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(error, RemoteError::Status { status: 404, .. }), "{error}");
+    }
+}
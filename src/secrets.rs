@@ -0,0 +1,64 @@
+//! Access tokens kept in the OS keyring (Keychain, Credential Manager or the
+//! Secret Service) instead of `profiles.json`, behind the `keyring` feature.
+
+use thiserror::Error;
+
+/// The keyring service every token is filed under; the profile name is the user.
+const SERVICE: &str = "pulumi-profile-selector";
+
+#[derive(Debug, Error)]
+pub enum KeyringError {
+    #[error("No access token for profile '{0}' in the OS keyring; store one with --set-token {0}")]
+    NoToken(String),
+    #[error("The OS keyring is unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl KeyringError {
+    fn from_keyring(name: &str, error: keyring::Error) -> Self {
+        match error {
+            keyring::Error::NoEntry => KeyringError::NoToken(name.to_string()),
+            other => KeyringError::Unavailable(other.to_string()),
+        }
+    }
+}
+
+fn entry(name: &str) -> Result<keyring::Entry, KeyringError> {
+    keyring::Entry::new(SERVICE, name).map_err(|e| KeyringError::from_keyring(name, e))
+}
+
+/// Stores `token` as the access token of profile `name`, replacing any previous one.
+pub fn store_token(name: &str, token: &str) -> Result<(), KeyringError> {
+    entry(name)?
+        .set_password(token)
+        .map_err(|e| KeyringError::from_keyring(name, e))
+}
+
+/// The access token stored for profile `name`.
+pub fn fetch_token(name: &str) -> Result<String, KeyringError> {
+    entry(name)?.get_password().map_err(|e| KeyringError::from_keyring(name, e))
+}
+
+/// Removes the access token stored for profile `name`; removing one that
+/// isn't there is not an error.
+pub fn delete_token(name: &str) -> Result<(), KeyringError> {
+    match entry(name)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(KeyringError::from_keyring(name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_entries_point_at_set_token() {
+        let error = KeyringError::from_keyring("dev", keyring::Error::NoEntry);
+        assert!(matches!(error, KeyringError::NoToken(ref name) if name == "dev"));
+        assert!(error.to_string().contains("--set-token dev"));
+
+        let error = KeyringError::from_keyring("dev", keyring::Error::NoStorageAccess("locked".into()));
+        assert!(matches!(error, KeyringError::Unavailable(_)));
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use pulumi_profile_selector::config::SORT_KEYS;
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Output formats accepted by `--format` and the `format` setting.
+pub const LIST_FORMATS: &[&str] = &["table", "plain", "json", "jsonl"];
+
+/// Shell names accepted by `--shell` and the `shell` setting.
+pub const SHELL_NAMES: &[&str] = &["bash", "zsh", "fish", "nu", "pwsh", "cmd", "posix"];
+
+/// Defaults for command-line flags, read from config.toml. Every field is
+/// optional; anything unset falls back to the built-in default.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub page_size: Option<usize>,
+    pub format: Option<String>,
+    /// `false` disables color like `--color never`; `true` leaves it to terminal detection.
+    pub color: Option<bool>,
+    pub shell: Option<String>,
+    pub sort: Option<String>,
+    /// Where `--audit-log` appends activations and deactivations.
+    pub audit_log: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Reads the settings file; a missing file means all defaults.
+    pub fn load(path: &Path) -> Result<Settings> {
+        if !path.exists() {
+            log::debug!("No settings file at {}", path.display());
+            return Ok(Settings::default());
+        }
+
+        log::debug!("Reading settings from {}", path.display());
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read settings file: {}", path.display()))?;
+        Settings::parse(&content).with_context(|| format!("Invalid settings file: {}", path.display()))
+    }
+
+    fn parse(content: &str) -> Result<Settings> {
+        let settings: Settings = toml::from_str(content)?;
+
+        if settings.page_size == Some(0) {
+            return Err(anyhow::anyhow!("page_size must be at least 1"));
+        }
+        if let Some(format) = &settings.format
+            && !LIST_FORMATS.contains(&format.as_str())
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown format '{}': expected one of {}",
+                format,
+                LIST_FORMATS.join(", ")
+            ));
+        }
+        if let Some(shell) = &settings.shell
+            && !SHELL_NAMES.contains(&shell.as_str())
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown shell '{}': expected one of {}",
+                shell,
+                SHELL_NAMES.join(", ")
+            ));
+        }
+
+        if let Some(sort) = &settings.sort
+            && !SORT_KEYS.contains(&sort.as_str())
+        {
+            return Err(anyhow::anyhow!(
+                "Unknown sort '{}': expected one of {}",
+                sort,
+                SORT_KEYS.join(", ")
+            ));
+        }
+
+        Ok(settings)
+    }
+
+    /// Keeps every value set here and takes the rest from `fallback`, so
+    /// `flags.or(file)` lets explicit flags win over the settings file.
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            page_size: self.page_size.or(fallback.page_size),
+            format: self.format.or(fallback.format),
+            color: self.color.or(fallback.color),
+            shell: self.shell.or(fallback.shell),
+            sort: self.sort.or(fallback.sort),
+            audit_log: self.audit_log.or(fallback.audit_log),
+        }
+    }
+}
+
+/// Resolves config.toml: `$PULUMI_HOME/pulumi-profile-selector.toml` when
+/// `PULUMI_HOME` is set, otherwise `pulumi-profile-selector/config.toml` under
+/// `$XDG_CONFIG_HOME` or `~/.config`.
+pub fn get_settings_path() -> Result<PathBuf> {
+    resolve_settings_path(
+        env::var_os("PULUMI_HOME"),
+        env::var_os("XDG_CONFIG_HOME"),
+        dirs::home_dir(),
+    )
+}
+
+fn resolve_settings_path(
+    pulumi_home: Option<OsString>,
+    xdg_config_home: Option<OsString>,
+    home_dir: Option<PathBuf>,
+) -> Result<PathBuf> {
+    if let Some(dir) = pulumi_home.filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(dir).join("pulumi-profile-selector.toml"));
+    }
+
+    let config_dir = match xdg_config_home.filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => home_dir
+            .ok_or_else(|| anyhow::anyhow!("Unable to determine home directory"))?
+            .join(".config"),
+    };
+
+    Ok(config_dir.join("pulumi-profile-selector").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings() {
+        let settings =
+            Settings::parse(
+                "page_size = 20\nformat = \"plain\"\ncolor = false\nshell = \"fish\"\nsort = \"recent\"\naudit_log = \"/var/log/pps.log\"\n",
+            )
+            .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                page_size: Some(20),
+                format: Some("plain".to_string()),
+                color: Some(false),
+                shell: Some("fish".to_string()),
+                sort: Some("recent".to_string()),
+                audit_log: Some(PathBuf::from("/var/log/pps.log")),
+            }
+        );
+
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        assert!(Settings::parse("page_size = 0").is_err());
+        assert!(Settings::parse("format = \"yaml\"").is_err());
+        assert!(Settings::parse("shell = \"tcsh\"").is_err());
+        assert!(Settings::parse("sort = \"size\"").is_err());
+        assert!(Settings::parse("pagesize = 20").is_err(), "typos must not be ignored");
+    }
+
+    #[test]
+    fn test_flags_take_precedence_over_file() {
+        let file = Settings {
+            page_size: Some(20),
+            format: Some("plain".to_string()),
+            color: Some(false),
+            shell: Some("fish".to_string()),
+            sort: None,
+            audit_log: None,
+        };
+        let flags = Settings {
+            page_size: Some(5),
+            shell: Some("zsh".to_string()),
+            ..Settings::default()
+        };
+
+        let merged = flags.or(file.clone());
+        assert_eq!(merged.page_size, Some(5));
+        assert_eq!(merged.shell.as_deref(), Some("zsh"));
+        assert_eq!(merged.format.as_deref(), Some("plain"));
+        assert_eq!(merged.color, Some(false));
+
+        assert_eq!(Settings::default().or(file.clone()), file);
+        assert_eq!(Settings::default().or(Settings::default()), Settings::default());
+    }
+
+    #[test]
+    fn test_missing_settings_file_means_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = Settings::load(&dir.path().join("config.toml")).unwrap();
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn test_settings_path() {
+        let home = Some(PathBuf::from("/home/me"));
+        assert_eq!(
+            resolve_settings_path(Some("/srv/pulumi".into()), Some("/xdg".into()), home.clone()).unwrap(),
+            PathBuf::from("/srv/pulumi/pulumi-profile-selector.toml")
+        );
+        assert_eq!(
+            resolve_settings_path(None, Some("/xdg".into()), home.clone()).unwrap(),
+            PathBuf::from("/xdg/pulumi-profile-selector/config.toml")
+        );
+        assert_eq!(
+            resolve_settings_path(Some("".into()), None, home).unwrap(),
+            PathBuf::from("/home/me/.config/pulumi-profile-selector/config.toml")
+        );
+    }
+}
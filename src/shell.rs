@@ -0,0 +1,358 @@
+use crate::config::MANAGED_ENV_VARS;
+use std::env;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+    Pwsh,
+    /// Windows cmd.exe
+    Cmd,
+    Posix,
+}
+
+/// `$SHELL` names a shell whose syntax isn't known, or isn't set at all.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum UnknownShell {
+    #[error("Unrecognized shell '{0}'; can't tell which syntax to emit")]
+    Unrecognized(String),
+    #[error("SHELL is not set; can't tell which syntax to emit")]
+    Unset,
+}
+
+/// Picks the output syntax, preferring an explicit `--shell` value over `$SHELL`.
+/// Rather than guessing, a shell that isn't recognized is an error.
+pub fn detect_shell(shell_override: Option<&str>) -> Result<ShellKind, UnknownShell> {
+    detect_shell_from(
+        shell_override,
+        env::var("SHELL").ok().as_deref(),
+        env::var_os("PSModulePath").is_some(),
+        env::var_os("ComSpec").is_some(),
+    )
+}
+
+fn detect_shell_from(
+    shell_override: Option<&str>,
+    shell_env: Option<&str>,
+    has_ps_module_path: bool,
+    has_comspec: bool,
+) -> Result<ShellKind, UnknownShell> {
+    match shell_override.or(shell_env).filter(|shell| !shell.is_empty()) {
+        Some(shell) => ShellKind::from_shell_path(shell).ok_or_else(|| UnknownShell::Unrecognized(shell.to_string())),
+        // SHELL is rarely set on Windows, where PSModulePath betrays PowerShell instead
+        None if has_ps_module_path => Ok(ShellKind::Pwsh),
+        // ComSpec is always set on Windows, so without PowerShell it means cmd.exe
+        None if has_comspec => Ok(ShellKind::Cmd),
+        None => Err(UnknownShell::Unset),
+    }
+}
+
+/// Formats variables as `KEY=VALUE` lines for env files. Values are written
+/// verbatim since env-file parsers disagree on quoting.
+pub fn format_env_lines(vars: &[(&str, String)]) -> String {
+    vars.iter()
+        .map(|(name, value)| format!("{name}={value}\n"))
+        .collect()
+}
+
+/// Comment lines naming the profile an env file was generated from and when,
+/// for `--export-env-file --header`. `#` lines are skipped by docker-compose
+/// and `docker --env-file` alike.
+pub fn format_env_header(profile_name: &str, generated_at: &str) -> String {
+    format!("# Pulumi profile: {profile_name}\n# Generated by pulumi-profile-selector at {generated_at}\n")
+}
+
+impl ShellKind {
+    /// Classifies a shell by the file stem of its path (or a bare name such as
+    /// `fish`), so `/usr/bin/fisher` isn't mistaken for fish. `None` for
+    /// shells whose syntax isn't known.
+    pub fn from_shell_path(shell: &str) -> Option<ShellKind> {
+        // Split on both separators so Windows paths classify correctly everywhere
+        let file_name = shell.rsplit(['/', '\\']).next().unwrap_or(shell).to_lowercase();
+        let stem = file_name.strip_suffix(".exe").unwrap_or(&file_name);
+
+        match stem {
+            "bash" => Some(ShellKind::Bash),
+            "zsh" => Some(ShellKind::Zsh),
+            "fish" => Some(ShellKind::Fish),
+            "nu" | "nushell" => Some(ShellKind::Nu),
+            "pwsh" | "powershell" => Some(ShellKind::Pwsh),
+            "cmd" => Some(ShellKind::Cmd),
+            "posix" | "sh" | "dash" | "ash" | "ksh" | "mksh" => Some(ShellKind::Posix),
+            _ => None,
+        }
+    }
+
+    /// The value is quoted so the shell takes it literally: profiles from
+    /// `PULUMI_PROFILES_JSON` or `--profiles-url` may hold `$(...)` and the like.
+    pub fn set_var_command(&self, name: &str, value: &str) -> String {
+        match self {
+            ShellKind::Bash | ShellKind::Zsh | ShellKind::Posix => {
+                format!("export {name}={}", quote_posix(value))
+            }
+            ShellKind::Fish => format!("set -gx {name} {}", quote_fish(value)),
+            ShellKind::Nu => format!("$env.{name} = {}", quote_nu(value)),
+            ShellKind::Pwsh => format!("$env:{name} = {}", quote_pwsh(value)),
+            // cmd keeps quotes as part of the value, so metacharacters are escaped instead
+            ShellKind::Cmd => format!("set {name}={}", escape_cmd(value)),
+        }
+    }
+
+    pub fn unset_var_command(&self, name: &str) -> String {
+        match self {
+            ShellKind::Bash | ShellKind::Zsh | ShellKind::Posix => format!("unset {name}"),
+            ShellKind::Fish => format!("set -e {name}"),
+            // Tolerate variables that were never set, e.g. a token for a non-cloud profile
+            ShellKind::Nu => format!("hide-env -i {name}"),
+            ShellKind::Pwsh => format!("Remove-Item Env:\\{name} -ErrorAction SilentlyContinue"),
+            ShellKind::Cmd => format!("set {name}="),
+        }
+    }
+
+    /// Commands exporting each `(name, value)` pair, joined for a single eval.
+    pub fn set_vars_command(&self, vars: &[(&str, String)]) -> String {
+        let commands: Vec<String> = vars
+            .iter()
+            .map(|(name, value)| self.set_var_command(name, value))
+            .collect();
+        self.join_commands(&commands)
+    }
+
+    /// Commands unsetting every variable in `MANAGED_ENV_VARS`.
+    pub fn unset_managed_vars_command(&self) -> String {
+        self.unset_vars_command(MANAGED_ENV_VARS)
+    }
+
+    /// Commands unsetting each of `names`, joined for a single eval.
+    pub fn unset_vars_command(&self, names: &[&str]) -> String {
+        let commands: Vec<String> = names.iter().map(|name| self.unset_var_command(name)).collect();
+        self.join_commands(&commands)
+    }
+
+    /// Nushell has no eval, so its wrapper parses one statement per line; cmd
+    /// output is run as a batch file, where each line is a command.
+    fn join_commands(&self, commands: &[String]) -> String {
+        match self {
+            ShellKind::Nu | ShellKind::Cmd => commands.join("\n"),
+            _ => commands.join("; "),
+        }
+    }
+}
+
+/// A value `check_shell_values` refuses, by variable name.
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("Value of {0} contains a line break, which can't be passed to the shell safely")]
+pub struct UnsafeValue(pub String);
+
+/// Checks that every value survives `set_vars_command`: a line break would
+/// end the statement early, and the cmd and nushell wrappers apply the output
+/// line by line.
+pub fn check_shell_values(vars: &[(&str, String)]) -> Result<(), UnsafeValue> {
+    match vars.iter().find(|(_, value)| value.contains(['\n', '\r'])) {
+        Some((name, _)) => Err(UnsafeValue(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Single quotes, inside which POSIX shells expand nothing; a quote closes
+/// them, is escaped and reopens them.
+fn quote_posix(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Fish single quotes, where only `\\` and `\'` are escapes.
+fn quote_fish(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// PowerShell single quotes, where a quote is written twice.
+fn quote_pwsh(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// A nushell raw string with one more `#` than any run following a quote in
+/// `value`, so nothing inside can close it early.
+fn quote_nu(value: &str) -> String {
+    let longest = value
+        .match_indices('\'')
+        .map(|(i, _)| value[i + 1..].chars().take_while(|&c| c == '#').count())
+        .max()
+        .unwrap_or(0);
+    let hashes = "#".repeat(longest + 1);
+    format!("r{hashes}'{value}'{hashes}")
+}
+
+/// Escapes a value for a line of a batch file: carets before the characters
+/// cmd.exe treats specially outside quotes, such as the `&` in
+/// `s3://bucket?region=x&awssdk=v2`, and `%` doubled so `%VAR%` isn't expanded.
+fn escape_cmd(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '%' => escaped.push_str("%%"),
+            '^' | '&' | '|' | '<' | '>' | '(' | ')' | '"' => {
+                escaped.push('^');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_override_beats_shell_env() {
+        assert_eq!(detect_shell_from(Some("fish"), Some("/bin/zsh"), false, false), Ok(ShellKind::Fish));
+        assert_eq!(detect_shell_from(Some("pwsh"), Some("/bin/bash"), false, false), Ok(ShellKind::Pwsh));
+        assert_eq!(detect_shell_from(Some("bash"), Some("/usr/bin/nu"), false, false), Ok(ShellKind::Bash));
+        assert_eq!(detect_shell_from(Some("posix"), Some("/bin/tcsh"), false, false), Ok(ShellKind::Posix));
+    }
+
+    #[test]
+    fn test_detect_from_shell_env() {
+        assert_eq!(detect_shell_from(None, Some("/bin/bash"), false, false), Ok(ShellKind::Bash));
+        assert_eq!(detect_shell_from(None, Some("/bin/zsh"), false, false), Ok(ShellKind::Zsh));
+        assert_eq!(detect_shell_from(None, Some("/usr/bin/fish"), false, false), Ok(ShellKind::Fish));
+        assert_eq!(detect_shell_from(None, Some("/usr/bin/nu"), false, false), Ok(ShellKind::Nu));
+        assert_eq!(detect_shell_from(None, None, true, false), Ok(ShellKind::Pwsh));
+        assert_eq!(detect_shell_from(None, None, true, true), Ok(ShellKind::Pwsh));
+        assert_eq!(detect_shell_from(None, None, false, true), Ok(ShellKind::Cmd));
+        assert_eq!(detect_shell_from(Some("cmd"), Some("/bin/bash"), false, false), Ok(ShellKind::Cmd));
+    }
+
+    #[test]
+    fn test_undetectable_shell_is_an_error() {
+        assert_eq!(detect_shell_from(None, None, false, false), Err(UnknownShell::Unset));
+        assert_eq!(detect_shell_from(None, Some(""), false, false), Err(UnknownShell::Unset));
+        assert_eq!(
+            detect_shell_from(None, Some("/usr/bin/xonsh"), false, false),
+            Err(UnknownShell::Unrecognized("/usr/bin/xonsh".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_from_shell_path_matches_file_stem() {
+        assert_eq!(ShellKind::from_shell_path("/usr/bin/fish"), Some(ShellKind::Fish));
+        assert_eq!(ShellKind::from_shell_path("/usr/bin/fisher"), None);
+        assert_eq!(ShellKind::from_shell_path("/opt/menu/bin/sh"), Some(ShellKind::Posix));
+        assert_eq!(ShellKind::from_shell_path("/home/me/.cargo/bin/nu"), Some(ShellKind::Nu));
+        assert_eq!(ShellKind::from_shell_path("C:\\Program Files\\PowerShell\\7\\pwsh.exe"), Some(ShellKind::Pwsh));
+        assert_eq!(ShellKind::from_shell_path("PowerShell.exe"), Some(ShellKind::Pwsh));
+        assert_eq!(ShellKind::from_shell_path("C:\\Windows\\System32\\cmd.exe"), Some(ShellKind::Cmd));
+        assert_eq!(ShellKind::from_shell_path("zsh"), Some(ShellKind::Zsh));
+        assert_eq!(ShellKind::from_shell_path("/bin/dash"), Some(ShellKind::Posix));
+        assert_eq!(ShellKind::from_shell_path("/bin/tcsh"), None);
+    }
+
+    #[test]
+    fn test_set_and_unset_syntax() {
+        let url = "s3://state";
+        let cases = [
+            (ShellKind::Bash, "export PULUMI_BACKEND_URL='s3://state'", "unset PULUMI_BACKEND_URL"),
+            (ShellKind::Zsh, "export PULUMI_BACKEND_URL='s3://state'", "unset PULUMI_BACKEND_URL"),
+            (ShellKind::Posix, "export PULUMI_BACKEND_URL='s3://state'", "unset PULUMI_BACKEND_URL"),
+            (ShellKind::Fish, "set -gx PULUMI_BACKEND_URL 's3://state'", "set -e PULUMI_BACKEND_URL"),
+            (ShellKind::Nu, "$env.PULUMI_BACKEND_URL = r#'s3://state'#", "hide-env -i PULUMI_BACKEND_URL"),
+            (ShellKind::Pwsh, "$env:PULUMI_BACKEND_URL = 's3://state'", "Remove-Item Env:\\PULUMI_BACKEND_URL -ErrorAction SilentlyContinue"),
+        ];
+
+        for (kind, set, unset) in cases {
+            assert_eq!(kind.set_var_command("PULUMI_BACKEND_URL", url), set);
+            assert_eq!(kind.unset_var_command("PULUMI_BACKEND_URL"), unset);
+        }
+    }
+
+    #[test]
+    fn test_values_are_taken_literally() {
+        let hostile = r#"s3://b$(touch x)`id`"\'"#;
+        assert_eq!(ShellKind::Bash.set_var_command("V", hostile), r#"export V='s3://b$(touch x)`id`"\'\'''"#);
+        assert_eq!(ShellKind::Fish.set_var_command("V", hostile), r#"set -gx V 's3://b$(touch x)`id`"\\\''"#);
+        assert_eq!(ShellKind::Pwsh.set_var_command("V", hostile), r#"$env:V = 's3://b$(touch x)`id`"\'''"#);
+        assert_eq!(ShellKind::Nu.set_var_command("V", hostile), r##"$env.V = r#'s3://b$(touch x)`id`"\''#"##);
+        assert_eq!(ShellKind::Nu.set_var_command("V", "a'#b'##"), "$env.V = r###'a'#b'##'###");
+        assert_eq!(ShellKind::Cmd.set_var_command("V", hostile), r#"set V=s3://b$^(touch x^)`id`^"\'"#);
+        assert_eq!(
+            ShellKind::Cmd.set_var_command("V", r#"%PATH%"&calc)(|x"#),
+            r#"set V=%%PATH%%^"^&calc^)^(^|x"#
+        );
+    }
+
+    #[test]
+    fn test_line_breaks_are_refused() {
+        let vars = vec![("PULUMI_BACKEND_URL", "s3://ok".to_string()), ("X", "a\nrm -rf ~".to_string())];
+        assert_eq!(check_shell_values(&vars), Err(UnsafeValue("X".to_string())));
+        assert_eq!(check_shell_values(&vars[..1]), Ok(()));
+    }
+
+    #[test]
+    fn test_cmd_syntax_is_unquoted() {
+        assert_eq!(ShellKind::Cmd.set_var_command("PULUMI_BACKEND_URL", "s3://state"), "set PULUMI_BACKEND_URL=s3://state");
+        assert_eq!(
+            ShellKind::Cmd.set_var_command("PULUMI_BACKEND_URL", "s3://state?region=eu-west-1&awssdk=v2"),
+            "set PULUMI_BACKEND_URL=s3://state?region=eu-west-1^&awssdk=v2"
+        );
+        assert_eq!(ShellKind::Cmd.unset_var_command("PULUMI_BACKEND_URL"), "set PULUMI_BACKEND_URL=");
+
+        let vars = vec![
+            ("PULUMI_BACKEND_URL", "https://api.pulumi.com".to_string()),
+            ("PULUMI_ACCESS_TOKEN", "pul-123".to_string()),
+        ];
+        assert_eq!(
+            ShellKind::Cmd.set_vars_command(&vars),
+            "set PULUMI_BACKEND_URL=https://api.pulumi.com\nset PULUMI_ACCESS_TOKEN=pul-123"
+        );
+    }
+
+    #[test]
+    fn test_unset_covers_every_managed_var() {
+        for kind in [
+            ShellKind::Bash,
+            ShellKind::Zsh,
+            ShellKind::Fish,
+            ShellKind::Nu,
+            ShellKind::Pwsh,
+            ShellKind::Cmd,
+            ShellKind::Posix,
+        ] {
+            let output = kind.unset_managed_vars_command();
+            for name in MANAGED_ENV_VARS {
+                assert!(output.contains(&kind.unset_var_command(name)), "{kind:?} misses {name}");
+            }
+        }
+
+        assert_eq!(
+            ShellKind::Bash.unset_managed_vars_command(),
+            "unset PULUMI_BACKEND_URL; unset PULUMI_ACCESS_TOKEN; unset PULUMI_CONFIG_PASSPHRASE_FILE"
+        );
+        assert_eq!(ShellKind::Nu.unset_managed_vars_command().lines().count(), MANAGED_ENV_VARS.len());
+    }
+
+    #[test]
+    fn test_format_env_lines_is_verbatim() {
+        let vars = vec![
+            ("PULUMI_BACKEND_URL", "s3://bucket/path?region=us-east-1&x=\"y\"".to_string()),
+            ("PULUMI_ACCESS_TOKEN", "pul-$abc".to_string()),
+        ];
+
+        assert_eq!(
+            format_env_lines(&vars),
+            "PULUMI_BACKEND_URL=s3://bucket/path?region=us-east-1&x=\"y\"\nPULUMI_ACCESS_TOKEN=pul-$abc\n"
+        );
+    }
+
+    #[test]
+    fn test_env_header_is_comments() {
+        let header = format_env_header("dev", "2026-01-02T03:04:05Z");
+        assert_eq!(
+            header,
+            "# Pulumi profile: dev\n# Generated by pulumi-profile-selector at 2026-01-02T03:04:05Z\n"
+        );
+        assert!(header.lines().all(|line| line.starts_with("# ")));
+    }
+}
@@ -0,0 +1,214 @@
+//! Reusable sets of profiles, kept in templates.json next to profiles.json,
+//! that `--apply-template` creates in one go, e.g. the profiles every new
+//! team member starts with.
+
+use crate::config::{
+    ConfigError, IoContext, Profile, Result, WriteOptions, check_new_profile_name, get_pulumi_home,
+    normalize_backend, update_profiles, validate_backend_url, validate_profile_name,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub name: String,
+    pub profiles: Vec<ProfileTemplate>,
+}
+
+/// A profile whose fields may contain `{{var}}` placeholders, filled in from
+/// `--var var=value` when the template is applied.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileTemplate {
+    pub name: String,
+    pub backend: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplatesFile {
+    templates: Vec<Template>,
+}
+
+/// What applying a template did, by profile name.
+#[derive(Debug, Default, PartialEq)]
+pub struct TemplateSummary {
+    pub created: Vec<String>,
+    /// Already defined, so left as they were
+    pub skipped: Vec<String>,
+}
+
+pub fn get_templates_path() -> Result<PathBuf> {
+    Ok(get_pulumi_home()?.join("templates.json"))
+}
+
+/// Reads the templates file; a missing file has no templates.
+pub fn read_templates(templates_path: &Path) -> Result<Vec<Template>> {
+    if !templates_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(templates_path)
+        .io_context(|| format!("Failed to read templates file: {templates_path:?}"))?;
+    let file: TemplatesFile = serde_json::from_str(&content).map_err(|source| ConfigError::Parse {
+        context: format!("Failed to parse templates file: {templates_path:?}"),
+        source,
+    })?;
+    Ok(file.templates)
+}
+
+/// Replaces every `{{var}}` (spaces inside the braces are allowed) with its
+/// value in `vars`. A placeholder without a value is an error rather than
+/// being left in a profile name or backend.
+pub fn substitute(text: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| ConfigError::InvalidTemplate(format!("Unterminated '{{{{' in '{text}'")))?;
+
+        let var = after[..end].trim();
+        let value = vars.get(var).ok_or_else(|| {
+            ConfigError::InvalidTemplate(format!("No value for {{{{{var}}}}} in '{text}'; pass --var {var}=VALUE"))
+        })?;
+        filled.push_str(value);
+        rest = &after[end + 2..];
+    }
+    filled.push_str(rest);
+
+    Ok(filled)
+}
+
+impl ProfileTemplate {
+    /// The profile this template describes, with its placeholders filled in.
+    pub fn instantiate(&self, vars: &BTreeMap<String, String>) -> Result<Profile> {
+        let mut profile = Profile::new(substitute(&self.name, vars)?, substitute(&self.backend, vars)?);
+        profile.description = self.description.as_deref().map(|d| substitute(d, vars)).transpose()?;
+        profile.tags = self.tags.iter().map(|tag| substitute(tag, vars)).collect::<Result<_>>()?;
+        Ok(profile)
+    }
+}
+
+/// Creates the profiles of `template`, skipping any whose name is already
+/// taken. Every profile is filled in and validated before anything is
+/// written, so a missing variable or bad backend changes nothing.
+pub fn apply_template(
+    profiles_path: &Path,
+    template: &Template,
+    vars: &BTreeMap<String, String>,
+    force: bool,
+    options: &WriteOptions,
+) -> Result<TemplateSummary> {
+    let mut incoming = Vec::with_capacity(template.profiles.len());
+    for profile_template in &template.profiles {
+        let mut profile = profile_template.instantiate(vars)?;
+        validate_profile_name(&profile.name)?;
+        if !force {
+            validate_backend_url(&profile.backend)?;
+        }
+        profile.backend = normalize_backend(&profile.backend);
+        incoming.push(profile);
+    }
+
+    update_profiles(profiles_path, options, |profiles| {
+        let mut summary = TemplateSummary::default();
+        for profile in incoming {
+            if check_new_profile_name(profiles, &profile.name, options.case_sensitive).is_err() {
+                summary.skipped.push(profile.name);
+            } else {
+                summary.created.push(profile.name.clone());
+                profiles.push(profile);
+            }
+        }
+        Ok(summary)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::read_pulumi_profiles;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_substitute_placeholders() {
+        let vars = vars(&[("user", "alice"), ("region", "eu-west-1")]);
+        assert_eq!(
+            substitute("s3://state-{{user}}/dev?region={{ region }}", &vars).unwrap(),
+            "s3://state-alice/dev?region=eu-west-1"
+        );
+        assert_eq!(substitute("no placeholders", &vars).unwrap(), "no placeholders");
+
+        let missing = substitute("{{user}}-{{team}}", &vars).unwrap_err();
+        assert!(missing.to_string().contains("--var team=VALUE"), "{missing}");
+        assert!(matches!(substitute("{{user", &vars), Err(ConfigError::InvalidTemplate(_))));
+    }
+
+    #[test]
+    fn test_apply_template_skips_existing_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        let options = WriteOptions::default();
+        let existing = Profile::new("alice-dev".to_string(), "s3://mine".to_string());
+        crate::config::add_profile(&path, existing, false, &options).unwrap();
+
+        let template = Template {
+            name: "onboarding".to_string(),
+            profiles: vec![
+                ProfileTemplate {
+                    name: "{{user}}-dev".to_string(),
+                    backend: "s3://state-{{user}}/dev".to_string(),
+                    description: None,
+                    tags: Vec::new(),
+                },
+                ProfileTemplate {
+                    name: "{{user}}-sandbox".to_string(),
+                    backend: "file://~/sandbox-{{user}}".to_string(),
+                    description: Some("Sandbox for {{user}}".to_string()),
+                    tags: vec!["sandbox".to_string()],
+                },
+            ],
+        };
+
+        let summary = apply_template(&path, &template, &vars(&[("user", "alice")]), false, &options).unwrap();
+        assert_eq!(summary.created, vec!["alice-sandbox"]);
+        assert_eq!(summary.skipped, vec!["alice-dev"]);
+
+        let profiles = read_pulumi_profiles(&path).unwrap();
+        assert_eq!(profiles[0].backend, "s3://mine", "existing profiles are left alone");
+        assert_eq!(profiles[1].description.as_deref(), Some("Sandbox for alice"));
+
+        // Nothing is written when a variable is missing
+        assert!(apply_template(&path, &template, &BTreeMap::new(), false, &options).is_err());
+        assert_eq!(read_pulumi_profiles(&path).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_read_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("templates.json");
+        assert!(read_templates(&path).unwrap().is_empty());
+
+        fs::write(
+            &path,
+            r#"{"templates": [{"name": "team", "profiles": [{"name": "{{user}}", "backend": "s3://x"}]}]}"#,
+        )
+        .unwrap();
+        let templates = read_templates(&path).unwrap();
+        assert_eq!(templates[0].name, "team");
+        assert_eq!(templates[0].profiles[0].name, "{{user}}");
+    }
+}
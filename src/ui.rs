@@ -1,56 +1,413 @@
-use crate::config::Profile;
+use pulumi_profile_selector::config::{
+    Profile, SortKey, backend_suggestions, check_new_profile_name, group_by_scheme, sort_profiles,
+};
 use anyhow::Result;
-use inquire::{InquireError, Select, Text};
+use inquire::CustomUserError;
+use inquire::validator::Validation;
+use inquire::{Confirm, InquireError, MultiSelect, Password, Select, Text};
+use std::env;
+use std::ffi::OsString;
+
+pub const DEFAULT_PAGE_SIZE: usize = 10;
+
+const ADD_NEW_OPTION: &str = "+ Add new profile...";
+
+/// Schemes offered by the guided backend prompt, with what to type after them.
+const GUIDED_SCHEMES: &[(&str, &str)] = &[
+    ("s3", "Bucket and optional path, e.g. my-bucket/state?region=eu-west-1"),
+    ("gs", "Bucket and optional path, e.g. my-bucket/state"),
+    ("azblob", "Container and optional path, e.g. state"),
+    ("file", "Directory, e.g. ~, ./state or /srv/pulumi"),
+    ("https", "Service URL, e.g. api.pulumi.com"),
+];
+
+const RAW_URL_OPTION: &str = "Enter a full URL...";
+
+/// What the user picked in the selector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    /// An existing profile, by name
+    Profile(String),
+    /// The `+ Add new profile...` entry
+    AddNew,
+}
+
+/// One line of the selector.
+#[derive(Clone, Copy)]
+enum Row {
+    Header,
+    Profile(usize),
+    AddNew,
+}
 
 pub struct ProfileSelector {
     profiles: Vec<Profile>,
+    active: Option<String>,
+    page_size: usize,
+    color: bool,
+    add_option: bool,
+    sort: SortKey,
+    remembered: Option<String>,
 }
 
 impl ProfileSelector {
-    pub fn new(profiles: Vec<Profile>) -> Self {
-        Self { profiles }
+    pub fn new(profiles: Vec<Profile>, active: Option<String>) -> Self {
+        Self {
+            profiles,
+            active,
+            page_size: DEFAULT_PAGE_SIZE,
+            color: false,
+            add_option: false,
+            sort: SortKey::default(),
+            remembered: None,
+        }
+    }
+
+    /// Opens on the profile picked last time, if it's still listed.
+    pub fn with_remembered(mut self, remembered: Option<String>) -> Self {
+        self.remembered = remembered;
+        self
+    }
+
+    /// Orders the profiles within each section; by name unless set.
+    pub fn with_sort(mut self, sort: SortKey) -> Self {
+        self.sort = sort;
+        self
     }
 
-    pub fn run(&mut self) -> Result<Option<String>> {
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Appends a `+ Add new profile...` entry below the profiles.
+    pub fn with_add_option(mut self, add_option: bool) -> Self {
+        self.add_option = add_option;
+        self
+    }
+
+    pub fn run(&mut self) -> Result<Option<Selection>> {
         if self.profiles.is_empty() {
             return Ok(None);
         }
 
-        let options: Vec<String> = self.profiles.iter().map(format_profile_display).collect();
+        // The pinned profile first, then the rest in sort order under their backend scheme
+        sort_profiles(&mut self.profiles, self.sort);
+        let (pinned, rest): (Vec<Profile>, Vec<Profile>) =
+            std::mem::take(&mut self.profiles).into_iter().partition(|p| p.pinned);
+        let groups = group_by_scheme(rest);
+        let show_headers = groups.len() > 1;
 
-        let ans = Select::new("Select Pulumi Profile:", options)
-            .with_page_size(10)
-            .with_help_message("↑↓ to move, enter to select, type to filter")
-            .prompt();
+        let mut sections: Vec<(Option<String>, Vec<Profile>)> = Vec::new();
+        if !pinned.is_empty() {
+            sections.push((None, pinned));
+        }
+        sections.extend(groups.into_iter().map(|(scheme, members)| (show_headers.then_some(scheme), members)));
+
+        // What each option stands for, since the displayed strings aren't unique
+        let active = self.active.as_deref();
+        let mut rows: Vec<Row> = Vec::new();
+        let mut options: Vec<String> = Vec::new();
+        for (header, members) in sections {
+            if let Some(scheme) = header {
+                rows.push(Row::Header);
+                options.push(dim(&format!("-- {scheme} --"), self.color));
+            }
+            for profile in members {
+                let is_active = active == Some(profile.name.as_str());
+                rows.push(Row::Profile(self.profiles.len()));
+                options.push(format_profile_display(&profile, is_active, self.color));
+                self.profiles.push(profile);
+            }
+        }
+        if self.add_option {
+            rows.push(Row::AddNew);
+            options.push(ADD_NEW_OPTION.to_string());
+        }
 
-        match ans {
-            Ok(selected_display) => {
-                // Find the profile that matches the selected display string
-                let selected_profile = self
-                    .profiles
-                    .iter()
-                    .find(|profile| format_profile_display(profile) == selected_display)
-                    .map(|profile| profile.name.clone());
+        // Start where the user left off so re-selecting is a single keypress
+        let profiles = &self.profiles;
+        let mut cursor = starting_cursor(&rows, profiles, self.remembered.as_deref(), active);
 
-                Ok(selected_profile)
+        // Match the query against the name and the backend, keeping the better score.
+        // Headers only show while the query is empty, and then everything keeps its
+        // order; the add entry always stays, last.
+        let scorer = |input: &str, _: &String, _: &str, index: usize| match rows[index] {
+            _ if input.is_empty() => Some(-(index as i64)),
+            Row::Profile(i) => fuzzy_score(input, &profiles[i].name).max(fuzzy_score(input, &profiles[i].backend)),
+            Row::Header => None,
+            Row::AddNew => Some(i64::MIN),
+        };
+
+        loop {
+            let ans = Select::new("Select Pulumi Profile:", options.clone())
+                .with_page_size(self.page_size)
+                .with_starting_cursor(cursor)
+                .with_scorer(&scorer)
+                .with_help_message("↑↓ to move, enter to select, type to fuzzy match name or backend")
+                .raw_prompt();
+
+            match ans {
+                Ok(selected) => match rows[selected.index] {
+                    Row::Profile(i) => return Ok(Some(Selection::Profile(profiles[i].name.clone()))),
+                    Row::AddNew => return Ok(Some(Selection::AddNew)),
+                    // A header isn't a profile, so ask again from the same spot
+                    Row::Header => cursor = selected.index,
+                },
+                Err(InquireError::OperationCanceled) => return Ok(None),
+                Err(InquireError::OperationInterrupted) => return Ok(None),
+                Err(e) => return Err(anyhow::anyhow!("Selection failed: {}", e)),
             }
-            Err(InquireError::OperationCanceled) => Ok(None),
-            Err(InquireError::OperationInterrupted) => Ok(None),
-            Err(e) => Err(anyhow::anyhow!("Selection failed: {}", e)),
         }
     }
 }
 
-pub fn prompt_for_profile_details() -> Result<(String, String)> {
-    let name = Text::new("Profile name:")
-        .with_help_message("Enter a unique name for this profile")
+/// Drives `--watch`: hands each selection from `select` to `apply` until
+/// `select` returns `None`, which is how the selector reports Esc or Ctrl-C.
+/// A failed `apply` is reported and the selector shown again, and backing out
+/// of a prompt inside it (say, the add form) just returns to the selector;
+/// only errors from `select` itself end the loop early. Returns how many
+/// selections were applied.
+pub fn run_watch_loop(
+    mut select: impl FnMut() -> Result<Option<Selection>>,
+    mut apply: impl FnMut(Selection) -> Result<()>,
+) -> Result<usize> {
+    let mut applied = 0;
+    while let Some(selection) = select()? {
+        match apply(selection) {
+            Ok(()) => applied += 1,
+            Err(e) if is_prompt_cancelled(&e) => {}
+            Err(e) => eprintln!("Error: {e:#}"),
+        }
+    }
+
+    Ok(applied)
+}
+
+/// The row the selector opens on: the profile picked last time, else the
+/// active one, else the first profile. A remembered name that isn't listed
+/// any more, e.g. after a delete or under `--tag`, is skipped.
+fn starting_cursor(rows: &[Row], profiles: &[Profile], remembered: Option<&str>, active: Option<&str>) -> usize {
+    let row_of = |name: Option<&str>| {
+        let name = name?;
+        rows.iter()
+            .position(|row| matches!(row, Row::Profile(i) if profiles[*i].name == name))
+    };
+    row_of(remembered)
+        .or_else(|| row_of(active))
+        .or_else(|| rows.iter().position(|row| matches!(row, Row::Profile(_))))
+        .unwrap_or(0)
+}
+
+/// Prompts for a new profile, asking only for the name and backend when they
+/// weren't already given on the command line. A typed name that is invalid or
+/// already among `existing` is rejected inline, keeping the user in the prompt.
+/// Names differing only in case count as taken unless `case_sensitive`.
+pub fn prompt_for_profile_details(
+    name: Option<String>,
+    backend: Option<String>,
+    existing: &[Profile],
+    case_sensitive: bool,
+) -> Result<Profile> {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let existing = existing.to_vec();
+            Text::new("Profile name:")
+                .with_help_message("Enter a unique name for this profile")
+                .with_validator(move |input: &str| Ok(validate_new_name(input.trim(), &existing, case_sensitive)))
+                .prompt()?
+                .trim()
+                .to_string()
+        }
+    };
+
+    let backend = match backend {
+        Some(backend) => backend,
+        None => prompt_for_backend_guided(existing)?,
+    };
+
+    let description = Text::new("Description:")
+        .with_help_message("Optional, shown next to the profile in the selector")
+        .prompt()?;
+
+    let mut profile = Profile::new(name, backend);
+    profile.description = Some(description.trim().to_string()).filter(|d| !d.is_empty());
+
+    if profile.is_cloud_backend() {
+        let token = Password::new("Access token:")
+            .with_help_message("Optional, exported as PULUMI_ACCESS_TOKEN with --current")
+            .without_confirmation()
+            .prompt()?;
+        profile.access_token = Some(token.trim().to_string()).filter(|t| !t.is_empty());
+    }
+
+    Ok(profile)
+}
+
+/// Asks for the backend's scheme first and then only for the part after
+/// `scheme://`, completing hosts already used by `existing` profiles. A raw
+/// URL option takes the whole URL instead.
+pub fn prompt_for_backend_guided(existing: &[Profile]) -> Result<String> {
+    let options: Vec<String> = GUIDED_SCHEMES
+        .iter()
+        .map(|(scheme, _)| format!("{scheme}://"))
+        .chain(std::iter::once(RAW_URL_OPTION.to_string()))
+        .collect();
+
+    let choice = Select::new("Backend type:", options)
+        .with_help_message("↑↓ to move, enter to select")
+        .raw_prompt()?;
+
+    let Some((scheme, hint)) = GUIDED_SCHEMES.get(choice.index) else {
+        let backend = Text::new("Backend URL:")
+            .with_help_message("e.g., s3://my-bucket/state, file://./state, https://api.pulumi.com")
+            .prompt()?;
+        return Ok(backend);
+    };
+
+    let hosts = backend_suggestions(existing, scheme);
+    let suggest = move |input: &str| -> Result<Vec<String>, CustomUserError> {
+        let input = input.to_lowercase();
+        Ok(hosts
+            .iter()
+            .filter(|host| host.to_lowercase().starts_with(&input))
+            .cloned()
+            .collect())
+    };
+    let remainder = Text::new(&format!("{scheme}://"))
+        .with_help_message(hint)
+        .with_autocomplete(suggest)
         .prompt()?;
+    Ok(assemble_backend(scheme, &remainder))
+}
 
-    let backend = Text::new("Backend URL:")
-        .with_help_message("e.g., s3://my-bucket/state, file://./state, https://api.pulumi.com")
+/// Joins a scheme and what was typed after it. A pasted `scheme://` prefix
+/// isn't doubled, and an https URL may be given in full.
+fn assemble_backend(scheme: &str, remainder: &str) -> String {
+    let remainder = remainder.trim();
+    let remainder = remainder
+        .strip_prefix(&format!("{scheme}://"))
+        .unwrap_or(remainder);
+    format!("{scheme}://{remainder}")
+}
+
+/// Validator for the name prompt: the reason `add_profile` would refuse `name`.
+fn validate_new_name(name: &str, existing: &[Profile], case_sensitive: bool) -> Validation {
+    match check_new_profile_name(existing, name, case_sensitive) {
+        Ok(()) => Validation::Valid,
+        Err(e) => Validation::Invalid(e.to_string().into()),
+    }
+}
+
+/// Lets the user pick which discovered profiles to import; all are checked by default.
+pub fn prompt_for_profiles_to_import(candidates: &[Profile]) -> Result<Vec<Profile>> {
+    let options: Vec<String> = candidates
+        .iter()
+        .map(|profile| format_profile_display(profile, false, false))
+        .collect();
+    let all: Vec<usize> = (0..options.len()).collect();
+
+    let selected = MultiSelect::new("Import these backends as profiles?", options)
+        .with_default(&all)
+        .with_help_message("↑↓ to move, space to toggle, enter to confirm")
+        .raw_prompt()?;
+
+    Ok(selected
+        .into_iter()
+        .map(|option| candidates[option.index].clone())
+        .collect())
+}
+
+/// Lets the user check several profiles to delete at once; returns their names.
+pub fn prompt_for_profiles_to_delete(profiles: &[Profile]) -> Result<Vec<String>> {
+    let options: Vec<String> = profiles
+        .iter()
+        .map(|profile| format_profile_display(profile, false, false))
+        .collect();
+
+    let selected = MultiSelect::new("Select profiles to delete:", options)
+        .with_help_message("↑↓ to move, space to toggle, enter to delete checked profiles")
+        .raw_prompt()?;
+
+    Ok(selected
+        .into_iter()
+        .map(|option| profiles[option.index].name.clone())
+        .collect())
+}
+
+/// Whether `err` comes from the user backing out of a prompt with Esc.
+pub fn is_prompt_cancelled(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<InquireError>(), Some(InquireError::OperationCanceled))
+}
+
+/// Full details of a profile, one field per line, including the parts the
+/// selector's one-line entries leave out or that get cut off in narrow terminals.
+pub fn profile_preview(profile: &Profile) -> String {
+    let mut preview = format!("Name:        {}\nBackend:     {}", profile.name, profile.backend);
+
+    if let Some(description) = &profile.description {
+        preview.push_str(&format!("\nDescription: {description}"));
+    }
+
+    if let Some(notes) = &profile.notes {
+        // Continuation lines line up under the first
+        preview.push_str(&format!("\nNotes:       {}", notes.replace('\n', "\n             ")));
+    }
+
+    if !profile.tags.is_empty() {
+        preview.push_str(&format!("\nTags:        {}", profile.tags.join(", ")));
+    }
+
+    if let Some(passphrase_file) = &profile.passphrase_file {
+        preview.push_str(&format!("\nPassphrase:  {}", passphrase_file.display()));
+    }
+
+    preview
+}
+
+/// Shows the chosen profile in full and asks whether to activate it. inquire
+/// can't attach a preview to the highlighted row, so it comes after selection.
+pub fn confirm_activation(profile: &Profile) -> Result<bool> {
+    eprintln!("{}", profile_preview(profile));
+
+    let confirmed = Confirm::new(&format!("Activate {} ({})?", profile.name, profile.backend))
+        .with_default(true)
+        .prompt()?;
+
+    Ok(confirmed)
+}
+
+pub fn confirm_deletion(profile: &Profile) -> Result<bool> {
+    let confirmed = Confirm::new("Delete this profile?")
+        .with_default(false)
+        .with_help_message(&format!("{} -> {}", profile.name, profile.backend))
+        .prompt()?;
+
+    Ok(confirmed)
+}
+
+pub fn confirm_edit_backend(profile_name: &str) -> Result<bool> {
+    let confirmed = Confirm::new(&format!("Change the backend URL of '{}' now?", profile_name))
+        .with_default(true)
+        .prompt()?;
+
+    Ok(confirmed)
+}
+
+/// Asks for the access token `--set-token` moves into the OS keyring.
+pub fn prompt_for_access_token(profile_name: &str) -> Result<String> {
+    let token = Password::new(&format!("Access token for '{profile_name}':"))
+        .with_help_message("Stored in the OS keyring, never in profiles.json")
+        .without_confirmation()
         .prompt()?;
 
-    Ok((name, backend))
+    Ok(token.trim().to_string())
 }
 
 pub fn prompt_for_backend_url() -> Result<String> {
@@ -61,6 +418,410 @@ pub fn prompt_for_backend_url() -> Result<String> {
     Ok(backend)
 }
 
-fn format_profile_display(profile: &Profile) -> String {
-    format!("{} -> {}", profile.name, profile.backend)
-}
\ No newline at end of file
+/// Asks for a new backend URL, starting from `current` so it can be tweaked
+/// in place rather than retyped.
+pub fn prompt_for_backend_url_with_default(current: &str) -> Result<String> {
+    let backend = Text::new("New backend URL:")
+        .with_initial_value(current)
+        .with_help_message("Edit the current URL, or clear it with ctrl+u and type a new one")
+        .prompt()?;
+
+    Ok(backend)
+}
+
+/// Case-insensitive subsequence match, so `pd` matches `prod`. Returns `None`
+/// when `query` isn't a subsequence of `candidate`; otherwise higher scores
+/// mean consecutive runs and matches at word starts, with gaps penalised.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut matched = 0;
+    let mut last_match: Option<usize> = None;
+
+    for (i, &c) in candidate.iter().enumerate() {
+        if matched == query.len() {
+            break;
+        }
+        if c != query[matched] {
+            continue;
+        }
+
+        score += 1;
+        if i == 0 || !candidate[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        match last_match {
+            Some(last) if last + 1 == i => score += 5,
+            Some(last) => score -= (i - last - 1) as i64,
+            None => score -= i as i64 / 4,
+        }
+
+        last_match = Some(i);
+        matched += 1;
+    }
+
+    (matched == query.len()).then_some(score)
+}
+
+/// Values of `--color`.
+pub const COLOR_CHOICES: &[&str] = &["auto", "always", "never"];
+
+/// When output carries ANSI colors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// On a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    /// Even when piped or with `NO_COLOR` set
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// The choice called `name` in `COLOR_CHOICES`.
+    pub fn from_name(name: &str) -> Option<ColorChoice> {
+        match name {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Whether output going to a terminal (`is_tty`) or not should carry ANSI
+/// colors. An explicit `--color always` or `never` wins over a non-empty
+/// `NO_COLOR`, which in turn wins over `auto`'s terminal detection.
+pub fn resolve_color(flag: ColorChoice, is_tty: bool) -> bool {
+    colorize_from(flag, env::var_os("NO_COLOR"), is_tty)
+}
+
+fn colorize_from(flag: ColorChoice, no_color_env: Option<OsString>, is_tty: bool) -> bool {
+    match flag {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => no_color_env.is_none_or(|value| value.is_empty()) && is_tty,
+    }
+}
+
+pub fn bold(text: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[1m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn dim(text: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[2m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+/// One profile as `name -> backend`, the format of `--list --format plain`.
+pub fn format_profile_line(profile: &Profile, color: bool) -> String {
+    format!("{} -> {}", bold(&profile.name, color), dim(&profile.backend, color))
+}
+
+fn format_profile_display(profile: &Profile, is_active: bool, color: bool) -> String {
+    let mut display = format_profile_line(profile, color);
+
+    if let Some(description) = &profile.description {
+        display.push_str(&format!("  ({description})"));
+    }
+
+    if !profile.tags.is_empty() {
+        display.push_str(&format!(" [{}]", profile.tags.join(", ")));
+    }
+
+    if profile.pinned {
+        display.push_str(" (pinned)");
+    }
+
+    if profile.default {
+        display.push_str(" (default)");
+    }
+
+    if is_active {
+        display.push_str(" (active)");
+    }
+
+    display
+}
+
+/// Width of the name column: the longest name, but never narrower than its header.
+fn name_column_width(profiles: &[(usize, &Profile)]) -> usize {
+    profiles
+        .iter()
+        .map(|(_, profile)| profile.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len())
+}
+
+/// Renders numbered profiles as a table with the names and backends aligned.
+pub fn format_profile_table(profiles: &[(usize, &Profile)], color: bool) -> String {
+    let width = name_column_width(profiles);
+    let index_width = profiles.iter().map(|(i, _)| i.to_string().len()).max().unwrap_or(1);
+
+    let mut table = format!("{:>index_width$}  {:<width$}  BACKEND\n", "#", "NAME");
+    for (index, profile) in profiles {
+        // Pad before coloring so escape codes don't count towards the width
+        let name = bold(&format!("{:<width$}", profile.name), color);
+        table.push_str(&format!("{index:>index_width$}  {}  {}\n", name, dim(&profile.backend, color)));
+    }
+
+    table
+}
+
+/// Renders profiles as JSON lines, one compact object per profile, for
+/// `--format jsonl`.
+pub fn format_profile_lines(profiles: &[(usize, &Profile)]) -> serde_json::Result<String> {
+    let mut lines = String::new();
+    for (_, profile) in profiles {
+        lines.push_str(&serde_json::to_string(profile)?);
+        lines.push('\n');
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulumi_profile_selector::config::ordered_profiles;
+
+    #[test]
+    fn test_name_validator_rejects_taken_and_invalid_names() {
+        let existing = [Profile::new("dev".to_string(), "s3://dev".to_string())];
+        let is_valid = |name: &str| validate_new_name(name, &existing, false) == Validation::Valid;
+
+        assert!(is_valid("prod"));
+        assert!(!is_valid("dev"));
+        assert!(!is_valid("DEV"), "names are case-insensitive by default");
+        assert_eq!(validate_new_name("DEV", &existing, true), Validation::Valid);
+        assert!(!is_valid("my profile"));
+        assert!(!is_valid(""));
+        assert_eq!(
+            validate_new_name("dev", &existing, false),
+            Validation::Invalid("Profile 'dev' already exists".into())
+        );
+    }
+
+    #[test]
+    fn test_assemble_backend_per_scheme() {
+        assert_eq!(assemble_backend("s3", "my-bucket/state?region=eu-west-1"), "s3://my-bucket/state?region=eu-west-1");
+        assert_eq!(assemble_backend("gs", " my-bucket "), "gs://my-bucket");
+        assert_eq!(assemble_backend("azblob", "state"), "azblob://state");
+        assert_eq!(assemble_backend("file", "~"), "file://~");
+        assert_eq!(assemble_backend("file", "/srv/pulumi"), "file:///srv/pulumi");
+        assert_eq!(assemble_backend("file", "./state"), "file://./state");
+        assert_eq!(assemble_backend("https", "api.pulumi.com"), "https://api.pulumi.com");
+        assert_eq!(assemble_backend("https", "https://pulumi.example.com"), "https://pulumi.example.com");
+        assert_eq!(assemble_backend("s3", "s3://pasted-bucket"), "s3://pasted-bucket");
+    }
+
+    #[test]
+    fn test_starting_cursor_falls_back_to_active_then_first() {
+        let profiles = [
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("prod".to_string(), "s3://prod".to_string()),
+        ];
+        let rows = [Row::Header, Row::Profile(0), Row::Profile(1), Row::AddNew];
+
+        assert_eq!(starting_cursor(&rows, &profiles, Some("prod"), Some("dev")), 2);
+        assert_eq!(starting_cursor(&rows, &profiles, Some("deleted"), Some("dev")), 1);
+        assert_eq!(starting_cursor(&rows, &profiles, Some("deleted"), None), 1, "first profile, not the header");
+        assert_eq!(starting_cursor(&rows, &profiles, None, Some("prod")), 2);
+        assert_eq!(starting_cursor(&[Row::AddNew], &[], Some("dev"), None), 0);
+    }
+
+    #[test]
+    fn test_fuzzy_score_matches_subsequences() {
+        assert!(fuzzy_score("pd", "prod").is_some());
+        assert!(fuzzy_score("PROD", "prod").is_some());
+        assert!(fuzzy_score("s3 dev", "s3://pulumi-state-dev").is_some());
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+        assert_eq!(fuzzy_score("dp", "prod"), None);
+        assert_eq!(fuzzy_score("xyz", "prod"), None);
+    }
+
+    #[test]
+    fn test_fuzzy_score_ranks_match_quality() {
+        let exact = fuzzy_score("dev", "dev").unwrap();
+        let prefix = fuzzy_score("dev", "development").unwrap();
+        let scattered = fuzzy_score("dev", "data-eu-vault").unwrap();
+        let late = fuzzy_score("dev", "team-infra-dev").unwrap();
+
+        assert!(exact >= prefix);
+        assert!(prefix > scattered);
+        assert!(late > scattered);
+    }
+
+    #[test]
+    fn test_watch_loop_runs_until_cancelled() {
+        let mut selections = vec![
+            Some(Selection::Profile("dev".to_string())),
+            Some(Selection::AddNew),
+            Some(Selection::Profile("broken".to_string())),
+            Some(Selection::Profile("prod".to_string())),
+            None,
+            Some(Selection::Profile("never".to_string())),
+        ]
+        .into_iter();
+        let mut seen = Vec::new();
+
+        let applied = run_watch_loop(
+            || Ok(selections.next().unwrap()),
+            |selection| {
+                seen.push(selection.clone());
+                match selection {
+                    Selection::AddNew => Err(InquireError::OperationCanceled.into()),
+                    Selection::Profile(name) if name == "broken" => Err(anyhow::anyhow!("empty backend")),
+                    Selection::Profile(_) => Ok(()),
+                }
+            },
+        )
+        .unwrap();
+
+        assert_eq!(applied, 2);
+        assert_eq!(seen.len(), 4, "nothing is applied after the cancel");
+        assert_eq!(selections.next(), Some(Some(Selection::Profile("never".to_string()))));
+    }
+
+    #[test]
+    fn test_watch_loop_stops_on_selector_errors() {
+        let mut calls = 0;
+        let result = run_watch_loop(
+            || {
+                calls += 1;
+                Err(anyhow::anyhow!("profiles.json is unreadable"))
+            },
+            |_| Ok(()),
+        );
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_table_aligns_backends() {
+        let profiles = [
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("production-eu".to_string(), "s3://prod".to_string()),
+        ];
+        let numbered = ordered_profiles(&profiles, SortKey::None);
+        assert_eq!(name_column_width(&numbered), 13);
+        assert_eq!(name_column_width(&numbered[..1]), "NAME".len());
+        assert_eq!(name_column_width(&[]), "NAME".len());
+
+        let table = format_profile_table(&numbered, false);
+        let columns: Vec<Option<usize>> = table
+            .lines()
+            .map(|line| line.find("s3://").or_else(|| line.find("BACKEND")))
+            .collect();
+        assert_eq!(columns, vec![Some(18); 3]);
+        assert!(table.lines().nth(2).unwrap().starts_with("2  production-eu"));
+    }
+
+    #[test]
+    fn test_sorted_table_numbers_from_the_top() {
+        // Twelve profiles in reverse name order, so sorting reverses them
+        let profiles: Vec<Profile> = (1..=12)
+            .rev()
+            .map(|n| Profile::new(format!("p-{n:02}"), format!("s3://{n}")))
+            .collect();
+
+        let table = format_profile_table(&ordered_profiles(&profiles, SortKey::Name), false);
+        let rows: Vec<&str> = table.lines().skip(1).collect();
+        assert_eq!(rows.len(), 12);
+        for (n, row) in (1..=12).zip(&rows) {
+            assert_eq!(*row, format!("{n:>2}  p-{n:02}  s3://{n}"));
+        }
+        assert!(table.starts_with(" #  NAME"));
+    }
+
+    #[test]
+    fn test_json_lines_parse_one_profile_each() {
+        let mut cloud = Profile::new("cloud".to_string(), "https://api.pulumi.com".to_string());
+        cloud.tags = vec!["prod".to_string()];
+        cloud.notes = Some("line one\nline two".to_string());
+        let profiles = [Profile::new("dev".to_string(), "s3://dev".to_string()), cloud];
+
+        let lines = format_profile_lines(&ordered_profiles(&profiles, SortKey::None)).unwrap();
+        assert!(lines.ends_with('\n'));
+        let parsed: Vec<Profile> = lines.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(parsed, profiles);
+        assert_eq!(format_profile_lines(&[]).unwrap(), "");
+    }
+
+    #[test]
+    fn test_colorize_decision() {
+        assert!(colorize_from(ColorChoice::Auto, None, true));
+        assert!(colorize_from(ColorChoice::Auto, Some(OsString::new()), true));
+        assert!(!colorize_from(ColorChoice::Auto, None, false));
+        assert!(!colorize_from(ColorChoice::Never, None, true));
+    }
+
+    #[test]
+    fn test_no_color_only_overrides_auto() {
+        let no_color = || Some(OsString::from("1"));
+        // NO_COLOR beats terminal detection...
+        assert!(!colorize_from(ColorChoice::Auto, no_color(), true));
+        // ...but not an explicit --color
+        assert!(colorize_from(ColorChoice::Always, no_color(), true));
+        assert!(colorize_from(ColorChoice::Always, no_color(), false));
+        assert!(!colorize_from(ColorChoice::Never, None, true));
+    }
+
+    #[test]
+    fn test_color_choice_names() {
+        for name in COLOR_CHOICES {
+            assert!(ColorChoice::from_name(name).is_some(), "{name}");
+        }
+        assert_eq!(ColorChoice::from_name("yes"), None);
+    }
+
+    #[test]
+    fn test_colored_table_keeps_alignment() {
+        let profiles = [
+            Profile::new("dev".to_string(), "s3://dev".to_string()),
+            Profile::new("production-eu".to_string(), "s3://prod".to_string()),
+        ];
+
+        let numbered = ordered_profiles(&profiles, SortKey::None);
+        let colored = format_profile_table(&numbered, true);
+        assert!(colored.contains("1  \x1b[1mdev          \x1b[0m  \x1b[2ms3://dev\x1b[0m"));
+        assert!(!format_profile_table(&numbered, false).contains('\x1b'));
+        assert_eq!(format_profile_line(&profiles[0], false), "dev -> s3://dev");
+    }
+
+    #[test]
+    fn test_profile_preview() {
+        let mut profile = Profile::new("dev".to_string(), "s3://a-very-long-bucket-name/with/a/deep/path".to_string());
+        assert_eq!(
+            profile_preview(&profile),
+            "Name:        dev\nBackend:     s3://a-very-long-bucket-name/with/a/deep/path"
+        );
+
+        profile.description = Some("Team sandbox".to_string());
+        profile.tags = vec!["dev".to_string(), "team".to_string()];
+        let preview = profile_preview(&profile);
+        assert!(preview.contains("\nDescription: Team sandbox"));
+        assert!(preview.ends_with("\nTags:        dev, team"));
+        assert!(!preview.contains("Passphrase"));
+
+        profile.notes = Some("Owned by infra\nAsk before destroying".to_string());
+        assert!(profile_preview(&profile).contains("\nNotes:       Owned by infra\n             Ask before destroying\n"));
+    }
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn test_activate_index_matches_the_listed_numbers() {
+    let home = tempfile::tempdir().unwrap();
+    // Twelve profiles, stored in reverse name order and with backends in yet another order
+    let profiles: Vec<String> = (1..=12)
+        .rev()
+        .map(|n| format!(r#"{{"name": "p-{n:02}", "backend": "s3://{}"}}"#, 13 - n))
+        .collect();
+    common::write_profiles(home.path(), &format!(r#"{{"version": 1, "profiles": [{}]}}"#, profiles.join(", ")));
+
+    for sort in ["name", "backend", "none"] {
+        let output = run(home.path(), &["--list", "--format", "plain", "--sort", sort]);
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let rows: Vec<(&str, &str)> = stdout
+            .lines()
+            .skip(1)
+            .map(|line| line.trim().split_once(". ").unwrap())
+            .collect();
+
+        let numbers: Vec<String> = rows.iter().map(|(number, _)| number.to_string()).collect();
+        let expected: Vec<String> = (1..=12).map(|n| n.to_string()).collect();
+        assert_eq!(numbers, expected, "--sort {sort}");
+
+        for (number, line) in rows {
+            let backend = line.split_once(" -> ").unwrap().1;
+            let output = run(
+                home.path(),
+                &["-c", "--shell", "bash", "--sort", sort, "--activate-index", number],
+            );
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                format!("export PULUMI_BACKEND_URL='{backend}'"),
+                "--sort {sort} --activate-index {number}"
+            );
+        }
+    }
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+fn run(home: &Path, args: &[&str]) -> Output {
+    common::command(home).args(args).env("USER", "alice").output().unwrap()
+}
+
+#[test]
+fn test_activation_and_deactivation_are_audited() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(home.path(), r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}]}"#);
+    let log = home.path().join("audit").join("activations.log");
+    let log_arg = log.to_str().unwrap();
+
+    assert!(run(home.path(), &["--audit-log", log_arg, "-a", "dev"]).status.success());
+    assert!(run(home.path(), &["--audit-log", log_arg, "-c", "--shell", "bash", "-a", "dev"]).status.success());
+    assert!(run(home.path(), &["--audit-log", log_arg, "--deactivate"]).status.success());
+
+    let content = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = content.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+    assert_eq!(lines, ["alice dev s3://dev", "alice dev s3://dev", "alice dev -"]);
+}
+
+#[test]
+fn test_unwritable_audit_log_only_warns() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(home.path(), r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}]}"#);
+    // A directory can't be appended to
+    let output = run(home.path(), &["--audit-log", home.path().to_str().unwrap(), "-a", "dev"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: failed to write the audit log"));
+    assert!(home.path().join("current_profile").exists());
+}
@@ -0,0 +1,24 @@
+// Each test binary uses only some of these
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// The binary with `home` as `PULUMI_HOME` and no profiles or backend taken from the environment.
+pub fn command(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pulumi-profile-selector"));
+    command
+        .env("PULUMI_HOME", home)
+        .env_remove("PULUMI_PROFILES_JSON")
+        .env_remove("PULUMI_BACKEND_URL");
+    command
+}
+
+pub fn run(home: &Path, args: &[&str]) -> Output {
+    command(home).args(args).output().unwrap()
+}
+
+/// Writes `json` as the profiles file in `home`.
+pub fn write_profiles(home: &Path, json: &str) {
+    std::fs::write(home.join("profiles.json"), json).unwrap();
+}
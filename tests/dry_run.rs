@@ -0,0 +1,56 @@
+// Dry-run mode is process-wide, so it's exercised through the binary
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+use common::run;
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_dry_run_leaves_files_untouched() {
+    let home = tempfile::tempdir().unwrap();
+    let profiles_path = home.path().join("profiles.json");
+    let current_path = home.path().join("current_profile");
+
+    assert!(run(home.path(), &["--add", "--name", "dev", "--backend", "s3://dev"]).status.success());
+    assert!(run(home.path(), &["-a", "dev"]).status.success());
+    let profiles_before = fs::read_to_string(&profiles_path).unwrap();
+    let current_before = fs::read_to_string(&current_path).unwrap();
+
+    let output = run(home.path(), &["--dry-run", "--add", "--name", "prod", "--backend", "s3://prod"]);
+    assert_eq!(stdout(&output).trim(), "Would add profile 'prod' -> s3://prod");
+
+    let output = run(home.path(), &["--dry-run", "--edit", "dev", "--backend", "gs://dev"]);
+    assert!(stdout(&output).contains("Would change the backend of profile 'dev' to gs://dev"));
+
+    let output = run(home.path(), &["--dry-run", "--rename", "dev", "development"]);
+    assert!(stdout(&output).contains("Would rename profile 'dev' to 'development'"));
+
+    let output = run(home.path(), &["--dry-run", "--delete", "dev", "--force"]);
+    assert!(stdout(&output).contains("Would delete profile 'dev'"));
+
+    let output = run(home.path(), &["--dry-run", "-c", "--shell", "bash", "-a", "dev"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).is_empty());
+
+    assert_eq!(fs::read_to_string(&profiles_path).unwrap(), profiles_before);
+    assert_eq!(fs::read_to_string(&current_path).unwrap(), current_before);
+    assert!(!home.path().join("profiles.json.bak").exists());
+}
+
+#[test]
+fn test_dry_run_still_reports_errors() {
+    let home = tempfile::tempdir().unwrap();
+
+    let output = run(home.path(), &["--dry-run", "--rename", "missing", "other"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Profile 'missing' not found"));
+
+    let output = run(home.path(), &["--dry-run", "--delete", "missing"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!home.path().join("profiles.json").exists());
+}
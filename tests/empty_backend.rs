@@ -0,0 +1,17 @@
+mod common;
+
+#[test]
+fn test_activating_an_empty_backend_fails() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(home.path(), r#"{"version": 1, "profiles": [{"name": "blank", "backend": ""}]}"#);
+
+    for args in [&["-a", "blank"][..], &["-c", "--shell", "bash", "-a", "blank"]] {
+        let output = common::run(home.path(), args);
+
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty(), "nothing may be exported for {args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Profile 'blank' has an empty backend; set one with --edit blank"), "{stderr}");
+    }
+    assert!(!home.path().join("current_profile").exists());
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn test_env_file_holds_unquoted_variables() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(
+        home.path(),
+        r#"{"version": 1, "profiles": [{
+            "name": "cloud",
+            "backend": "https://api.pulumi.com",
+            "access_token": "pul-$abc",
+            "env": {"AWS_REGION": "eu-west-1"}
+        }]}"#,
+    );
+    let env_file = home.path().join(".env");
+    let expected = "PULUMI_BACKEND_URL=https://api.pulumi.com\nPULUMI_ACCESS_TOKEN=pul-$abc\nAWS_REGION=eu-west-1\n";
+
+    let output = run(home.path(), &["--export-env-file", "cloud", "--out", env_file.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(&env_file).unwrap(), expected);
+
+    // Without --out the same lines go to stdout
+    let output = run(home.path(), &["--export-env-file", "cloud"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = run(home.path(), &["--export-env-file", "cloud", "--header", "--out", env_file.to_str().unwrap()]);
+    assert!(output.status.success());
+    let content = std::fs::read_to_string(&env_file).unwrap();
+    let (header, variables): (Vec<&str>, Vec<&str>) = content.lines().partition(|line| line.starts_with('#'));
+    assert_eq!(header[0], "# Pulumi profile: cloud");
+    assert!(header[1].starts_with("# Generated by pulumi-profile-selector at ") && header[1].ends_with('Z'));
+    assert_eq!(variables.join("\n") + "\n", expected);
+
+    let output = run(home.path(), &["--export-env-file", "missing"]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use std::path::Path;
+
+use common::run;
+
+fn write_profiles(home: &Path) {
+    let profiles = r#"{"version": 1, "profiles": [
+        {"name": "eu", "backend": "s3://eu-state", "env": {"AWS_REGION": "eu-west-1", "AWS_PROFILE": "state"}},
+        {"name": "gcp", "backend": "gs://gcp-state", "env": {"CLOUDSDK_CORE_PROJECT": "infra"}},
+        {"name": "plain", "backend": "file:///tmp/state"}
+    ]}"#;
+    common::write_profiles(home, profiles);
+}
+
+#[test]
+fn test_activation_exports_extra_vars() {
+    let home = tempfile::tempdir().unwrap();
+    write_profiles(home.path());
+
+    let output = run(home.path(), &["-c", "--shell", "bash", "-a", "eu"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "export PULUMI_BACKEND_URL='s3://eu-state'; export AWS_PROFILE='state'; export AWS_REGION='eu-west-1'"
+    );
+}
+
+#[test]
+fn test_deactivation_unsets_every_extra_var() {
+    let home = tempfile::tempdir().unwrap();
+    write_profiles(home.path());
+
+    let output = run(home.path(), &["-c", "--shell", "bash", "--deactivate"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    for name in ["PULUMI_BACKEND_URL", "AWS_PROFILE", "AWS_REGION", "CLOUDSDK_CORE_PROJECT"] {
+        assert!(stdout.contains(&format!("unset {name}")), "{name} is not unset in {stdout}");
+    }
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::process::{Command, Output};
+
+const INLINE: &str = r#"[{"name": "ci", "backend": "s3://ci-state"}, {"name": "prod", "backend": "s3://prod"}]"#;
+
+// Runs the binary with PULUMI_PROFILES_JSON set and an empty PULUMI_HOME, so a
+// process of its own keeps the variable away from the unit tests' file reads
+fn run_inline(home: &std::path::Path, args: &[&str]) -> Output {
+    common::command(home)
+        .args(args)
+        .env("PULUMI_PROFILES_JSON", INLINE)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_profiles_come_from_env() {
+    let home = tempfile::tempdir().unwrap();
+
+    let output = run_inline(home.path(), &["--list", "--format", "json"]);
+    assert!(output.status.success());
+    let profiles: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(profiles[0]["name"], "ci");
+    assert_eq!(profiles[1]["backend"], "s3://prod");
+
+    let output = run_inline(home.path(), &["--count"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "2");
+
+    let output = run_inline(home.path(), &["--current", "--shell", "bash", "--activate", "ci"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("export PULUMI_BACKEND_URL='s3://ci-state'"));
+
+    assert_eq!(std::fs::read_dir(home.path()).unwrap().count(), 0, "nothing may be written");
+}
+
+#[test]
+fn test_env_profiles_cannot_be_modified() {
+    let home = tempfile::tempdir().unwrap();
+
+    let output = run_inline(home.path(), &["--add", "--name", "dev", "--backend", "s3://dev"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot modify profiles provided via PULUMI_PROFILES_JSON"));
+
+    let output = run_inline(home.path(), &["--rename", "ci", "ci-old"]);
+    assert!(!output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hostile_backend_is_not_executed() {
+    let home = tempfile::tempdir().unwrap();
+    let marker = home.path().join("pwned");
+    let backend = format!("s3://b$(touch {})`touch {}`'\"", marker.display(), marker.display());
+    let inline = serde_json::json!([{"name": "x", "backend": backend}]).to_string();
+
+    let output = common::command(home.path())
+        .args(["--current", "--activate", "x", "--shell", "posix"])
+        .env("PULUMI_PROFILES_JSON", &inline)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Apply the output the way the init wrapper does
+    let script = "eval \"$1\"; printf %s \"$PULUMI_BACKEND_URL\"";
+    let applied = Command::new("sh")
+        .args(["-c", script, "sh", &String::from_utf8_lossy(&output.stdout)])
+        .output()
+        .unwrap();
+    assert!(applied.status.success(), "{}", String::from_utf8_lossy(&applied.stderr));
+    assert_eq!(String::from_utf8_lossy(&applied.stdout), backend);
+    assert!(!marker.exists(), "the backend was executed");
+
+    let inline = serde_json::json!([{"name": "x", "backend": "s3://b\nrm -rf ~"}]).to_string();
+    let output = common::command(home.path())
+        .args(["--current", "--activate", "x", "--shell", "posix"])
+        .env("PULUMI_PROFILES_JSON", &inline)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line break"));
+}
@@ -0,0 +1,25 @@
+use pulumi_profile_selector::shell::ShellKind;
+use pulumi_profile_selector::{
+    Profile, WriteOptions, add_profile, delete_profile, edit_profile, read_pulumi_profiles,
+};
+
+// The crate's public API, as another program would use it
+#[test]
+fn test_manage_profiles_through_library() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("profiles.json");
+    let options = WriteOptions::default();
+
+    add_profile(&path, Profile::new("dev".to_string(), "s3://state-dev".to_string()), false, &options).unwrap();
+    edit_profile(&path, "dev", "gs://state-dev".to_string(), false, &options).unwrap();
+
+    let profiles = read_pulumi_profiles(&path).unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(
+        ShellKind::Bash.set_vars_command(&profiles[0].env_vars()),
+        "export PULUMI_BACKEND_URL='gs://state-dev'"
+    );
+
+    delete_profile(&path, "dev", &options).unwrap();
+    assert!(read_pulumi_profiles(&path).unwrap().is_empty());
+}
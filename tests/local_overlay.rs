@@ -0,0 +1,32 @@
+mod common;
+
+use common::run;
+use std::fs;
+
+#[test]
+fn test_overlay_only_profiles_point_to_local() {
+    let home = tempfile::tempdir().unwrap();
+    let base = r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}]}"#;
+    common::write_profiles(home.path(), base);
+    let local_path = home.path().join("profiles.local.json");
+    fs::write(&local_path, r#"{"version": 1, "profiles": [{"name": "mine", "backend": "s3://mine"}]}"#).unwrap();
+
+    let commands: [&[&str]; 4] = [
+        &["--delete", "mine", "--yes"],
+        &["--edit", "mine", "--backend", "s3://other"],
+        &["--rename", "mine", "ours"],
+        &["--edit", "mine"],
+    ];
+    for args in commands {
+        let output = run(home.path(), args);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("Profile 'mine' is defined in the local overlay; pass --local"), "{args:?}: {stderr}");
+    }
+    assert_eq!(fs::read_to_string(home.path().join("profiles.json")).unwrap(), base);
+
+    // With --local the same changes go to the overlay
+    assert!(run(home.path(), &["--local", "--rename", "mine", "ours"]).status.success());
+    assert!(run(home.path(), &["--local", "--delete", "ours", "--yes"]).status.success());
+    assert!(!fs::read_to_string(&local_path).unwrap().contains("ours"));
+}
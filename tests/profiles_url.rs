@@ -0,0 +1,43 @@
+#![cfg(feature = "remote")]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+use common::run;
+
+const SHARED: &str = r#"{"version": 1, "profiles": [{"name": "shared", "backend": "s3://team-state"}]}"#;
+
+/// Serves `SHARED` to every request on a local port, returning its URL.
+fn serve_profiles() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/profiles.json", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{SHARED}", SHARED.len());
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    url
+}
+
+#[test]
+fn test_profiles_come_from_url() {
+    let home = tempfile::tempdir().unwrap();
+    let url = serve_profiles();
+
+    let output = run(home.path(), &["--profiles-url", &url, "--list", "--format", "json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let profiles: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(profiles[0]["name"], "shared");
+    assert_eq!(profiles[0]["backend"], "s3://team-state");
+
+    let output = run(home.path(), &["--profiles-url", &url, "--add", "--name", "dev", "--backend", "s3://dev"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot modify profiles fetched from"));
+
+    assert_eq!(std::fs::read_dir(home.path()).unwrap().count(), 0, "nothing may be written");
+}
@@ -0,0 +1,30 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn test_quiet_activation_prints_nothing() {
+    let home = tempfile::tempdir().unwrap();
+
+    let output = run(home.path(), &["-q", "--add", "--name", "dev", "--backend", "s3://dev"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = run(home.path(), &["--quiet", "-a", "dev"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        std::fs::read_to_string(home.path().join("current_profile")).unwrap(),
+        r#"{"name":"dev","backend":"s3://dev"}"#
+    );
+
+    // The exports are the point of --current, so they still print
+    let output = run(home.path(), &["-q", "-c", "--shell", "bash", "-a", "dev"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "export PULUMI_BACKEND_URL='s3://dev'");
+
+    // Errors still reach stderr
+    let output = run(home.path(), &["-q", "-a", "missing"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'missing' not found"));
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+fn run(home: &Path, args: &[&str], backend: Option<&str>) -> Output {
+    let mut command = common::command(home);
+    command.args(args);
+    if let Some(backend) = backend {
+        command.env("PULUMI_BACKEND_URL", backend);
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn test_rotate_follows_the_exported_backend_in_current_mode() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(
+        home.path(),
+        r#"{"version": 1, "profiles": [{"name": "prod", "backend": "s3://prod"}, {"name": "dev", "backend": "s3://dev"}]}"#,
+    );
+
+    // Nothing exported: start at the first profile by name
+    let output = run(home.path(), &["-c", "--shell", "bash", "--rotate"], None);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "export PULUMI_BACKEND_URL='s3://dev'");
+
+    let output = run(home.path(), &["-c", "--shell", "bash", "--rotate"], Some("s3://prod"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "export PULUMI_BACKEND_URL='s3://dev'");
+
+    let output = run(home.path(), &["-c", "--shell", "bash", "--rotate-back"], Some("s3://dev"));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "export PULUMI_BACKEND_URL='s3://prod'");
+    assert!(!home.path().join("current_profile").exists());
+}
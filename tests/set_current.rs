@@ -0,0 +1,27 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn test_set_current_only_writes_the_file() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(
+        home.path(),
+        r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev"}, {"name": "production", "backend": "s3://prod"}]}"#,
+    );
+    let current = home.path().join("current_profile");
+
+    let output = run(home.path(), &["-q", "--set-current", "dev"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read_to_string(&current).unwrap(), r#"{"name":"dev","backend":"s3://dev"}"#);
+    assert!(!home.path().join("history.json").exists(), "setting the file isn't an activation");
+
+    // Unlike --activate, the name must match exactly
+    let output = run(home.path(), &["--set-current", "prod"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(std::fs::read_to_string(&current).unwrap(), r#"{"name":"dev","backend":"s3://dev"}"#);
+
+    let output = run(home.path(), &["--set-current", "dev", "--current"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+fn status(home: &Path, env_backend: Option<&str>) -> Output {
+    let mut command = common::command(home);
+    command.arg("--status");
+    if let Some(backend) = env_backend {
+        command.env("PULUMI_BACKEND_URL", backend);
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn test_status_warns_about_a_stale_backend_export() {
+    let home = tempfile::tempdir().unwrap();
+    common::write_profiles(
+        home.path(),
+        r#"{"version": 1, "profiles": [{"name": "dev", "backend": "s3://dev-state"}]}"#,
+    );
+    std::fs::write(home.path().join("current_profile"), "dev").unwrap();
+
+    for env_backend in [None, Some("s3://dev-state")] {
+        let output = status(home.path(), env_backend);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Backend: s3://dev-state"));
+        assert!(output.stderr.is_empty(), "{env_backend:?} should not warn");
+    }
+
+    let output = status(home.path(), Some("s3://prod-state"));
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("PULUMI_BACKEND_URL in this shell is s3://prod-state"), "{stderr}");
+    assert!(stderr.contains("--current"), "{stderr}");
+}